@@ -0,0 +1,202 @@
+//! Throughput and memory of `PaymentEngine` for generated workloads.
+//!
+//! Every workload is generated with a fixed seed, so the runs are comparable between commits:
+//! `cargo bench --bench engine -- --save-baseline main` before a change, `--baseline main` after it.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use criterion::{criterion_group, BatchSize, BenchmarkId, Criterion, Throughput};
+use csv::ReaderBuilder;
+use payment_engine::config::{EngineConfig, EngineMode, InputFormat};
+use payment_engine::generator::{Generator, GeneratorConfig, TypeWeights};
+use payment_engine::payment_engine::PaymentEngine;
+use payment_engine::transaction::TransactionEntity;
+use tokio::runtime::Runtime;
+
+const ROWS: usize = 100_000;
+const CLIENTS: u16 = 10_000;
+const SEED: u64 = 42;
+
+// Counts the live heap bytes and their peak, the engine runs are measured against the bytes live before them
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            grow(new_size);
+        }
+        new_ptr
+    }
+}
+
+fn grow(size: usize) {
+    let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(allocated, Ordering::Relaxed);
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn transactions(clients: u16, weights: TypeWeights) -> Vec<TransactionEntity> {
+    let config = GeneratorConfig {
+        rows: ROWS,
+        clients,
+        seed: SEED,
+        weights,
+        partial_dispute_ratio: 0.2,
+        ..Default::default()
+    };
+
+    let mut input = Vec::new();
+    Generator::new(config).write(InputFormat::Csv, &mut input).unwrap();
+
+    ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input.as_slice())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn modes() -> Vec<(String, EngineMode)> {
+    let shards = thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+
+    vec![
+        ("actor".to_string(), EngineMode::ActorPerClient),
+        (format!("sharded-{}", shards), EngineMode::Sharded(shards)),
+        ("synchronous".to_string(), EngineMode::Synchronous),
+    ]
+}
+
+// Outcomes are dropped instead of logged, so the rejected rows don't turn the benchmark into one of stderr
+async fn run(config: EngineConfig, transactions: Vec<TransactionEntity>) -> PaymentEngine {
+    let mut engine = PaymentEngine::with_config(config);
+    for transaction in transactions {
+        engine.submit_transaction(transaction).await.unwrap();
+    }
+
+    engine.shutdown().await.unwrap();
+    engine
+}
+
+fn bench_run(c: &mut Criterion, group_name: &str, cases: Vec<(String, EngineConfig, Vec<TransactionEntity>)>) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group(group_name);
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(ROWS as u64));
+
+    for (name, config, transactions) in cases {
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter_batched(
+                || transactions.clone(),
+                |transactions| runtime.block_on(run(config.clone(), transactions)),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+fn engine_modes(c: &mut Criterion) {
+    let transactions = transactions(CLIENTS, TypeWeights::default());
+    let cases = modes().into_iter()
+        .map(|(name, mode)| (name, EngineConfig { mode, ..Default::default() }, transactions.clone()))
+        .collect();
+
+    bench_run(c, "modes", cases);
+}
+
+fn client_count(c: &mut Criterion) {
+    let mut cases = Vec::new();
+    for clients in [10, 1_000, u16::MAX - 1] {
+        let transactions = transactions(clients, TypeWeights::default());
+        for (name, mode) in modes() {
+            cases.push((format!("{}/{}", name, clients), EngineConfig { mode, ..Default::default() }, transactions.clone()));
+        }
+    }
+
+    bench_run(c, "clients", cases);
+}
+
+fn dispute_ratio(c: &mut Criterion) {
+    let mixes = [
+        ("none", TypeWeights { deposit: 55, withdrawal: 45, dispute: 0, resolve: 0, chargeback: 0, transfer: 0 }),
+        ("default", TypeWeights::default()),
+        ("heavy", TypeWeights { deposit: 30, withdrawal: 25, dispute: 25, resolve: 12, chargeback: 8, transfer: 0 }),
+    ];
+
+    let cases = mixes.into_iter()
+        .map(|(name, weights)| (name.to_string(), EngineConfig::default(), transactions(CLIENTS, weights)))
+        .collect();
+
+    bench_run(c, "disputes", cases);
+}
+
+fn channel_size(c: &mut Criterion) {
+    let transactions = transactions(CLIENTS, TypeWeights::default());
+    let mut cases = Vec::new();
+    for (name, mode) in modes().into_iter().filter(|(_, mode)| *mode != EngineMode::Synchronous) {
+        for size in [1, 10, 100, 1000] {
+            let config = EngineConfig { mode, channel_size: Some(size), ..Default::default() };
+            cases.push((format!("{}/{}", name, size), config, transactions.clone()));
+        }
+    }
+
+    bench_run(c, "channel_size", cases);
+}
+
+// Peak heap while the workload is processed and the heap kept by the engine after it, both above the input
+fn memory_report() {
+    let runtime = Runtime::new().unwrap();
+
+    println!("\nmemory (MiB)              peak  retained");
+    for clients in [10, 1_000, u16::MAX - 1] {
+        let transactions = transactions(clients, TypeWeights::default());
+        for (name, mode) in modes() {
+            let input = transactions.clone();
+            let base = ALLOCATED.load(Ordering::Relaxed);
+            PEAK.store(base, Ordering::Relaxed);
+
+            let engine = runtime.block_on(run(EngineConfig { mode, ..Default::default() }, input));
+            let retained = ALLOCATED.load(Ordering::Relaxed).saturating_sub(base);
+            let peak = PEAK.load(Ordering::Relaxed).saturating_sub(base);
+            drop(engine);
+
+            println!("{:<22} {:>8.1} {:>9.1}", format!("{}/{}", name, clients), mib(peak), mib(retained));
+        }
+    }
+}
+
+fn mib(bytes: usize) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+criterion_group!(benches, engine_modes, client_count, dispute_ratio, channel_size);
+
+fn main() {
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+    memory_report();
+}
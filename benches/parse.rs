@@ -0,0 +1,68 @@
+//! Parsing speed of the generated transaction CSV with serde and with the fast path of `RecordParser`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use csv::{ByteRecord, ReaderBuilder, StringRecord};
+use payment_engine::config::InputFormat;
+use payment_engine::fast_csv::RecordParser;
+use payment_engine::generator::{Generator, GeneratorConfig, TypeWeights};
+use payment_engine::transaction::TransactionEntity;
+
+const ROWS: usize = 100_000;
+
+fn input() -> Vec<u8> {
+    let config = GeneratorConfig {
+        rows: ROWS,
+        clients: 10_000,
+        seed: 42,
+        weights: TypeWeights { transfer: 5, ..Default::default() },
+        ..Default::default()
+    };
+
+    let mut input = Vec::new();
+    Generator::new(config).write(InputFormat::Csv, &mut input).unwrap();
+    input
+}
+
+fn reader(input: &[u8], trim: csv::Trim) -> csv::Reader<&[u8]> {
+    ReaderBuilder::new()
+        .trim(trim)
+        .flexible(true)
+        .from_reader(input)
+}
+
+fn parse(c: &mut Criterion) {
+    let input = input();
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(ROWS as u64));
+
+    group.bench_function("serde", |b| {
+        b.iter(|| {
+            let mut reader = reader(&input, csv::Trim::All);
+            let headers = reader.headers().unwrap().clone();
+            let mut record = StringRecord::new();
+            let mut parsed = 0;
+            while reader.read_record(&mut record).unwrap() {
+                parsed += record.deserialize::<TransactionEntity>(Some(&headers)).is_ok() as usize;
+            }
+            parsed
+        })
+    });
+
+    group.bench_function("fast", |b| {
+        b.iter(|| {
+            let mut reader = reader(&input, csv::Trim::Headers);
+            let parser = RecordParser::new(reader.headers().unwrap());
+            let mut record = ByteRecord::new();
+            let mut parsed = 0;
+            while reader.read_byte_record(&mut record).unwrap() {
+                parsed += parser.parse(&record).unwrap().is_ok() as usize;
+            }
+            parsed
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -1,60 +1,145 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use rust_decimal::Decimal;
-use serde::Serialize;
-use tokio::sync::mpsc;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use tokio::sync::{mpsc, oneshot};
 use std::error::Error;
-
-use crate::decimal::serialize_decimal;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::config::{EngineConfig, SupervisionPolicy};
+use crate::currency::{Currency, DEFAULT_CURRENCY};
+use crate::decimal::Rounded;
+use crate::fees::FeeSchedule;
+use crate::history::HistoryEntry;
+use crate::ledger::{Ledger, LedgerAccount};
+use crate::limits::LimitTracker;
+use crate::precision::Precision;
 use crate::transaction::{Transaction, TransactionEntity, TransactionStatus, TransactionType};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub struct AccountEntity {
     pub client: u16,
-    #[serde(serialize_with = "serialize_decimal")]
+    // Skipped when the input has no currency column, so the output keeps the single currency format
+    pub currency: Option<Currency>,
     pub available: Decimal,
-    #[serde(serialize_with = "serialize_decimal")]
     pub held: Decimal,
-    #[serde(serialize_with = "serialize_decimal")]
     pub total: Decimal,
+    // Fees charged so far, present only when a fee schedule is configured
+    pub fees: Option<Decimal>,
+    // Present only when credit limits are enabled
+    pub credit_limit: Option<Decimal>,
+    pub remaining_credit: Option<Decimal>,
     pub locked: bool,
+    /// Precision of the currency, the amounts are rounded to it when written
+    pub precision: Precision,
+}
+
+impl Serialize for AccountEntity {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entity = serializer.serialize_struct("AccountEntity", 9)?;
+        entity.serialize_field("client", &self.client)?;
+        match self.currency.as_ref() {
+            Some(currency) => entity.serialize_field("currency", currency)?,
+            None => entity.skip_field("currency")?,
+        }
+        entity.serialize_field("available", &Rounded(self.available, self.precision))?;
+        entity.serialize_field("held", &Rounded(self.held, self.precision))?;
+        entity.serialize_field("total", &Rounded(self.total, self.precision))?;
+        for (name, amount) in [("fees", self.fees), ("credit_limit", self.credit_limit), ("remaining_credit", self.remaining_credit)] {
+            match amount {
+                Some(amount) => entity.serialize_field(name, &Rounded(amount, self.precision))?,
+                None => entity.skip_field(name)?,
+            }
+        }
+        entity.serialize_field("locked", &self.locked)?;
+        entity.end()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Balance {
+    pub held: Decimal,
+    pub total: Decimal,
+    pub fees: Decimal,
+    pub credit_limit: Decimal,
+}
+
+impl Balance {
+    pub fn available(&self) -> Decimal {
+        self.total - self.held
+    }
+
+    /// Funds which can be withdrawn, the available ones plus the credit line
+    pub fn spendable(&self) -> Decimal {
+        self.available() + self.credit_limit
+    }
+
+    pub fn remaining_credit(&self) -> Decimal {
+        let used = (-self.available()).max(Decimal::new(0, 0));
+        (self.credit_limit - used).max(Decimal::new(0, 0))
+    }
 }
 
 pub struct Account {
     client: u16,
-    held: Decimal,
-    total: Decimal,
+    balances: BTreeMap<Currency, Balance>,
     locked: bool,
 
     transactions: HashMap<u32, Transaction>,
+    history: Option<Vec<HistoryEntry>>,
+    ledger: Option<Ledger>,
+    limits: LimitTracker,
+    // Timestamp of the latest applied transaction, timestamps of a client never go backwards
+    last_timestamp: Option<u64>,
+    // Sequence number of the latest transaction which reached the account, the engine delivers them in order
+    last_seq: Option<u64>,
+    // Applied transactions in order, kept only to rebuild the account after a panic
+    journal: Option<Vec<(TransactionEntity, Option<TransferLeg>)>>,
+    faulted: bool,
+    config: Arc<EngineConfig>,
 }
 
+// Entity of the default currency, use `Account::entities` to get all the currencies
 impl From<&Account> for AccountEntity {
     fn from(account: &Account) -> Self {
         AccountEntity {
-            client: account.client,
-            available: account.available(),
-            held: account.held,
-            total: account.total,
-            locked: account.locked,
+            currency: None,
+            ..account.entity(DEFAULT_CURRENCY, &account.balance(DEFAULT_CURRENCY))
         }
     }
 }
 
 impl Account {
     pub fn new(client: u16) -> Self {
+        Self::with_config(client, Arc::new(EngineConfig::default()))
+    }
+
+    pub fn with_config(client: u16, config: Arc<EngineConfig>) -> Self {
+        let mut balances = BTreeMap::new();
+        let limits = config.credit_limits.as_ref().and_then(|limits| limits.get(&client));
+        for (currency, limit) in limits.into_iter().flatten() {
+            balances.insert(currency.clone(), Balance { credit_limit: *limit, ..Default::default() });
+        }
+
         Account {
             client,
-            held: Decimal::new(0, 0),
-            total: Decimal::new(0, 0),
+            balances,
             locked: false,
             transactions: HashMap::new(),
+            history: if config.history { Some(Vec::new()) } else { None },
+            ledger: if config.double_entry { Some(Ledger::new()) } else { None },
+            limits: LimitTracker::new(config.limits.for_client(client)),
+            last_timestamp: None,
+            last_seq: None,
+            journal: (config.supervision == SupervisionPolicy::Rebuild).then(Vec::new),
+            faulted: false,
+            config,
         }
     }
 
     pub fn available(&self) -> Decimal {
-        self.total - self.held
+        self.balance(DEFAULT_CURRENCY).available()
     }
 
     pub fn add_transaction(&mut self, tx: u32, transaction: Transaction) {
@@ -67,24 +152,128 @@ impl Account {
     }
 
     pub fn held(&self) -> Decimal {
-        self.held
+        self.balance(DEFAULT_CURRENCY).held
     }
 
     pub fn total(&self) -> Decimal {
-        self.total
+        self.balance(DEFAULT_CURRENCY).total
     }
 
     pub fn locked(&self) -> bool {
         self.locked
     }
 
-    // Setters
+    pub fn balance(&self, currency: &str) -> Balance {
+        self.balances.get(currency).copied().unwrap_or_default()
+    }
+
+    pub fn balances(&self) -> &BTreeMap<Currency, Balance> {
+        &self.balances
+    }
+
+    /// One entity per currency, an account without any balance is reported in the default currency
+    pub fn entities(&self) -> Vec<AccountEntity> {
+        if self.balances.is_empty() {
+            return vec![AccountEntity {
+                currency: Some(DEFAULT_CURRENCY.to_string()),
+                ..AccountEntity::from(self)
+            }];
+        }
+
+        self.balances.iter()
+            .map(|(currency, balance)| self.entity(currency, balance))
+            .collect()
+    }
+
+    fn entity(&self, currency: &str, balance: &Balance) -> AccountEntity {
+        let credit_enabled = self.config.credit_limits.is_some();
+
+        AccountEntity {
+            client: self.client,
+            currency: Some(currency.to_string()),
+            available: balance.available(),
+            held: balance.held,
+            total: balance.total,
+            fees: self.config.fees.as_ref().map(|_| balance.fees),
+            credit_limit: credit_enabled.then_some(balance.credit_limit),
+            remaining_credit: credit_enabled.then(|| balance.remaining_credit()),
+            locked: self.locked,
+            precision: self.config.precision.for_currency(currency),
+        }
+    }
+
+    /// Applied operations in order, `None` if history is disabled in the config
+    pub fn history(&self) -> Option<&[HistoryEntry]> {
+        self.history.as_deref()
+    }
+
+    /// Balance right after the transactions up to the sequence number `seq` were applied, in the currency of the last
+    /// of them. Entries without a sequence number (accounts used without the engine) are not found.
+    pub fn entity_after(&self, seq: u64) -> Result<AccountEntity, Box<dyn Error>> {
+        let history = match self.history.as_ref() {
+            Some(history) => history,
+            None => return Err("History is not enabled".into()),
+        };
+
+        // A dispute or a resolve has the same tx as the deposit, only the sequence number tells them apart
+        let entry = match history.iter().rev().find(|entry| entry.seq.is_some_and(|entry_seq| entry_seq <= seq)) {
+            Some(entry) => entry,
+            None => return Err(format!("No transaction of client {} up to sequence number {} in the history", self.client, seq).into()),
+        };
+
+        Ok(AccountEntity {
+            client: self.client,
+            currency: Some(entry.currency.clone()),
+            available: entry.available_after,
+            held: entry.held_after,
+            total: entry.total_after,
+            fees: None,
+            credit_limit: None,
+            remaining_credit: None,
+            locked: entry.locked,
+            precision: entry.precision,
+        })
+    }
+
+    /// Double-entry postings of the account, `None` if the mode is disabled in the config
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.ledger.as_ref()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn ledger_mut(&mut self) -> Option<&mut Ledger> {
+        self.ledger.as_mut()
+    }
+
+    /// Checks that the ledger agrees with the balances of the account
+    pub fn reconcile_ledger(&self) -> Result<(), Box<dyn Error>> {
+        let ledger = match self.ledger.as_ref() {
+            Some(ledger) => ledger,
+            None => return Ok(()),
+        };
+
+        for (currency, balance) in self.balances.iter() {
+            let available = ledger.balance(LedgerAccount::ClientAvailable(self.client), currency);
+            let held = ledger.balance(LedgerAccount::ClientHeld(self.client), currency);
+
+            if available != balance.available() || held != balance.held {
+                return Err(format!(
+                    "Ledger of client {} does not match the account in {}: ledger available {} held {}, account available {} held {}",
+                    self.client, currency, available, held, balance.available(), balance.held
+                ).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Setters, work with the default currency
     pub fn set_held(&mut self, held: Decimal) {
-        self.held = held;
+        self.balance_mut(DEFAULT_CURRENCY).held = held;
     }
 
     pub fn set_total(&mut self, total: Decimal) {
-        self.total = total;
+        self.balance_mut(DEFAULT_CURRENCY).total = total;
     }
 
     pub fn lock(&mut self) {
@@ -96,15 +285,196 @@ impl Account {
     }
 
     pub fn process_transaction(&mut self, transaction_entity: TransactionEntity) -> Result<(), Box<dyn Error>> {
+        self.process(transaction_entity, None)
+    }
+
+    /// Applies one side of a transfer, the engine coordinates the sides between the accounts
+    pub fn process_transfer(&mut self, transaction_entity: TransactionEntity, leg: TransferLeg) -> Result<(), Box<dyn Error>> {
+        self.process(transaction_entity, Some(leg))
+    }
+
+    fn process(&mut self, transaction_entity: TransactionEntity, leg: Option<TransferLeg>) -> Result<(), Box<dyn Error>> {
+        if self.faulted {
+            return Err("Account is faulted".into());
+        }
+
+        // Both sides of a transfer rollback share the sequence number, so only going backwards is an error
+        if let (Some(seq), Some(last_seq)) = (transaction_entity.seq, self.last_seq) {
+            if seq < last_seq {
+                return Err(format!("Sequence number {} is earlier than the previous one {}", seq, last_seq).into());
+            }
+        }
+        self.last_seq = transaction_entity.seq.or(self.last_seq);
+
+        let tx = transaction_entity.tx;
+        let journal_entry = self.journal.is_some().then(|| transaction_entity.clone());
+        let result = self.apply_transaction(transaction_entity, leg);
+
+        if self.config.check_invariants {
+            if let Err(e) = self.check_invariants() {
+                panic!("Invariant violated for client {} after tx {}: {}", self.client, tx, e);
+            }
+        }
+
+        if let (Ok(()), Some(journal), Some(entity)) = (&result, self.journal.as_mut(), journal_entry) {
+            journal.push((entity, leg));
+        }
+
+        result
+    }
+
+    pub fn is_faulted(&self) -> bool {
+        self.faulted
+    }
+
+    /// Brings the account to a usable state after a panic left it in an unknown one, according to the supervision policy
+    pub fn recover(&mut self) {
+        let journal = match self.journal.take() {
+            Some(journal) if self.config.supervision == SupervisionPolicy::Rebuild => journal,
+            _ => {
+                self.faulted = true;
+                return;
+            }
+        };
+
+        let mut account = Account::with_config(self.client, self.config.clone());
+        for (entity, leg) in journal {
+            // Journaled transactions were applied before, so they are applied again the same way
+            if let Err(e) = account.process(entity, leg) {
+                eprintln!("Error replaying the journal of client {}: {}", self.client, e);
+            }
+        }
+
+        *self = account;
+    }
+
+    /// Checks the balances against each other and against the stored transactions
+    pub fn check_invariants(&self) -> Result<(), Box<dyn Error>> {
+        for (currency, balance) in self.balances.iter() {
+            if balance.held < Decimal::new(0, 0) {
+                return Err(format!("held {} {} is negative", balance.held, currency).into());
+            }
+
+            // Only a credit line of the balance or the chargeback penalty, which locks the account, take the available
+            // funds below zero
+            let overdraft_allowed = balance.credit_limit > Decimal::ZERO || self.locked;
+            if balance.total < balance.held && !overdraft_allowed {
+                return Err(format!("total {} {} is less than held {}", balance.total, currency, balance.held).into());
+            }
+
+            let disputed: Decimal = self.transactions.values()
+                .filter(|transaction| &transaction.currency == currency)
+                .map(|transaction| transaction.disputed)
+                .sum();
+
+            if balance.held != disputed {
+                return Err(format!("held {} {} is not equal to the sum of disputed transactions {}", balance.held, currency, disputed).into());
+            }
+        }
+
+        self.reconcile_ledger()
+    }
+
+    fn apply_transaction(&mut self, mut transaction_entity: TransactionEntity, leg: Option<TransferLeg>) -> Result<(), Box<dyn Error>> {
+        // Disputes work in the currency of the referenced transaction
+        let currency = match transaction_entity.transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer | TransactionType::CreditLimit => {
+                transaction_entity.currency().to_string()
+            }
+            _ => match self.transactions.get(&transaction_entity.tx) {
+                Some(transaction) => transaction.currency.clone(),
+                None => DEFAULT_CURRENCY.to_string(),
+            },
+        };
+        if let Some(amount) = transaction_entity.amount {
+            transaction_entity.amount = Some(self.config.precision.apply(amount, &currency)?);
+        }
+
+        if let (Some(timestamp), Some(last_timestamp)) = (transaction_entity.timestamp, self.last_timestamp) {
+            if timestamp < last_timestamp {
+                return Err(format!("Timestamp {} is earlier than the previous one {}", timestamp, last_timestamp).into());
+            }
+        }
+        let timestamp = transaction_entity.timestamp.or(self.last_timestamp);
+
+        // Risk controls go before anything is applied
+        let amount = transaction_entity.amount.unwrap_or(Decimal::new(0, 0));
+        let withdrawn = match (transaction_entity.transaction_type, leg) {
+            (TransactionType::Withdrawal, _) | (TransactionType::Transfer, Some(TransferLeg::Debit)) => amount,
+            (TransactionType::Transfer, Some(TransferLeg::Rollback)) => -amount,
+            _ => Decimal::new(0, 0),
+        };
+        match (transaction_entity.transaction_type, leg) {
+            (TransactionType::Deposit, _) => self.limits.check_deposit(&currency, amount)?,
+            (TransactionType::Withdrawal, _) | (TransactionType::Transfer, Some(TransferLeg::Debit)) => self.limits.check_withdrawal(&currency, amount, timestamp)?,
+            _ => {}
+        }
+
+        let before = self.balance(&currency);
+        let status_before = self.transaction_status(transaction_entity.tx);
+
         match transaction_entity.transaction_type {
             TransactionType::Deposit => self.handle_deposit(&transaction_entity),
             TransactionType::Withdrawal => self.handle_withdrawal(&transaction_entity),
             TransactionType::Dispute => self.handle_dispute(&transaction_entity),
             TransactionType::Resolve => self.handle_resolve(&transaction_entity),
             TransactionType::Chargeback => self.handle_chargeback(&transaction_entity),
+            TransactionType::CreditLimit => self.handle_credit_limit(&transaction_entity),
+            TransactionType::Transfer => match leg {
+                Some(TransferLeg::Debit) => self.handle_transfer_debit(&transaction_entity),
+                Some(TransferLeg::Credit) => self.handle_transfer_credit(&transaction_entity),
+                Some(TransferLeg::Rollback) => self.handle_transfer_rollback(&transaction_entity),
+                None => Err("Transfer is processed by the engine".into()),
+            },
+        }?;
+
+        self.limits.record(&currency, withdrawn, timestamp);
+        self.last_timestamp = timestamp;
+
+        if self.history.is_some() {
+            let after = self.balance(&currency);
+            let precision = self.config.precision.for_currency(&currency);
+            let entry = HistoryEntry {
+                seq: transaction_entity.seq,
+                client: self.client,
+                tx: transaction_entity.tx,
+                transaction_type: transaction_entity.transaction_type,
+                currency,
+                available_before: before.available(),
+                held_before: before.held,
+                total_before: before.total,
+                available_after: after.available(),
+                held_after: after.held,
+                total_after: after.total,
+                status_before,
+                status_after: self.transaction_status(transaction_entity.tx),
+                locked: self.locked,
+                leg,
+                precision,
+            };
+
+            if let Some(history) = self.history.as_mut() {
+                history.push(entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn balance_mut(&mut self, currency: &str) -> &mut Balance {
+        self.balances.entry(currency.to_string()).or_default()
+    }
+
+    fn post(&mut self, tx: u32, currency: &str, from: LedgerAccount, to: LedgerAccount, amount: Decimal) {
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.post(tx, currency, from, to, amount);
         }
     }
 
+    fn transaction_status(&self, tx: u32) -> Option<TransactionStatus> {
+        self.transactions.get(&tx).map(|transaction| transaction.status)
+    }
+
     fn handle_deposit(&mut self, transaction_entity: &TransactionEntity) -> Result<(), Box<dyn Error>> {
         if self.locked() {
             return Err("Account is locked".into());
@@ -117,7 +487,9 @@ impl Account {
             return Err("Deposit amount is negative".into());
         }
 
-        self.total += amount;
+        let currency = transaction_entity.currency();
+        self.balance_mut(currency).total += amount;
+        self.post(transaction_entity.tx, currency, LedgerAccount::Settlement, LedgerAccount::ClientAvailable(self.client), amount);
 
         // If I correctly understand the task, the only deposit transactions could be disputed
         // so we save only deposit transactions
@@ -128,16 +500,21 @@ impl Account {
 
     fn handle_withdrawal(&mut self, transaction_entity: &TransactionEntity) -> Result<(), Box<dyn Error>> {
         let amount = transaction_entity.amount.unwrap_or(Decimal::new(0, 0));
+        let currency = transaction_entity.currency();
 
         if self.locked() {
             return Err("Account is locked".into());
         }
 
-        if amount.is_zero() || amount.is_sign_negative() || amount > self.available() {
+        let fee = self.fee(currency, |fees| fees.withdrawal_fee(amount));
+
+        if amount.is_zero() || amount.is_sign_negative() || amount + fee > self.balance(currency).spendable() {
             return Err("Withdrawal amount is invalid".into());
         }
 
-        self.total -= amount;
+        self.balance_mut(currency).total -= amount;
+        self.post(transaction_entity.tx, currency, LedgerAccount::ClientAvailable(self.client), LedgerAccount::Settlement, amount);
+        self.charge_fee(transaction_entity.tx, currency, fee);
 
         Ok(())
     }
@@ -147,38 +524,53 @@ impl Account {
             return Err("Account is locked".into());
         }
 
-        let available = self.available();
-        let disputed_tx = match self.transactions.get_mut(&transaction_entity.tx) {
+        let disputed_tx = match self.transactions.get(&transaction_entity.tx) {
             Some(tx) => tx,
             None => return Err("Transaction not found".into()),
         };
 
-        if disputed_tx.status != TransactionStatus::Normal {
+        if disputed_tx.status == TransactionStatus::Chargebacked || disputed_tx.undisputed() <= Decimal::new(0, 0) {
             return Err("Transaction is already disputed".into());
         }
 
-        let amount = disputed_tx.amount.unwrap_or(Decimal::new(0, 0));
-        
-        if amount.is_sign_negative() {
-            return Err("Transaction amount is negative".into());
+        // Rows without the timestamp are disputed at the time of the latest transaction of the client
+        let disputed_at = transaction_entity.timestamp.or(self.last_timestamp);
+        if let (Some(window), Some(disputed_at), Some(created_at)) = (self.config.dispute_window, disputed_at, disputed_tx.timestamp) {
+            if disputed_at.saturating_sub(created_at) > window.as_secs() {
+                return Err("Dispute window has expired".into());
+            }
+        }
+
+        // Rows without an amount dispute the rest of the transaction
+        let amount = transaction_entity.amount.unwrap_or(disputed_tx.undisputed());
+        let currency = disputed_tx.currency.clone();
+
+        if amount.is_zero() || amount.is_sign_negative() {
+            return Err("Dispute amount is invalid".into());
+        }
+
+        if amount > disputed_tx.undisputed() {
+            return Err("Dispute amount is greater than the undisputed part of the transaction".into());
         }
 
-        if amount > available {
+        if amount > self.balance(&currency).available() {
             return Err("Transaction amount is greater than available funds".into());
         }
 
-        disputed_tx.status = TransactionStatus::Disputed;
-        self.held += amount;
+        if let Some(transaction) = self.transactions.get_mut(&transaction_entity.tx) {
+            transaction.disputed += amount;
+            transaction.status = TransactionStatus::Disputed;
+        }
+        self.balance_mut(&currency).held += amount;
+        self.post(transaction_entity.tx, &currency, LedgerAccount::ClientAvailable(self.client), LedgerAccount::ClientHeld(self.client), amount);
 
         Ok(())
     }
 
+    // Open disputes are settled on locked accounts as well, a partial chargeback locks the account with the rest
+    // of the transaction still held
     fn handle_resolve(&mut self, transaction_entity: &TransactionEntity) -> Result<(), Box<dyn Error>> {
-        if self.locked() {
-            return Err("Account is locked".into());
-        }
-
-        let disputed_tx = match self.transactions.get_mut(&transaction_entity.tx) {
+        let disputed_tx = match self.transactions.get(&transaction_entity.tx) {
             Some(tx) => tx,
             None => return Err("Transaction not found".into()),
         };
@@ -187,18 +579,25 @@ impl Account {
             return Err("Transaction is not disputed".into());
         }
 
-        disputed_tx.status = TransactionStatus::Resolved;
-        self.held -= disputed_tx.amount.unwrap_or(Decimal::new(0, 0));
+        let amount = Self::disputed_amount(disputed_tx, transaction_entity)?;
+        let currency = disputed_tx.currency.clone();
+
+        if let Some(transaction) = self.transactions.get_mut(&transaction_entity.tx) {
+            transaction.disputed -= amount;
+            transaction.resolved += amount;
+            // The transaction stays disputed until all of its open disputes are settled
+            if transaction.disputed.is_zero() {
+                transaction.status = if transaction.charged_back.is_zero() { TransactionStatus::Resolved } else { TransactionStatus::Chargebacked };
+            }
+        }
+        self.balance_mut(&currency).held -= amount;
+        self.post(transaction_entity.tx, &currency, LedgerAccount::ClientHeld(self.client), LedgerAccount::ClientAvailable(self.client), amount);
 
         Ok(())
     }
 
     fn handle_chargeback(&mut self, transaction_entity: &TransactionEntity) -> Result<(), Box<dyn Error>> {
-        if self.locked() {
-            return Err("Account is locked".into());
-        }
-
-        let disputed_tx = match self.transactions.get_mut(&transaction_entity.tx) {
+        let disputed_tx = match self.transactions.get(&transaction_entity.tx) {
             Some(tx) => tx,
             None => return Err("Transaction not found".into()),
         };
@@ -207,45 +606,249 @@ impl Account {
             return Err("Transaction is not disputed".into());
         }
 
-        disputed_tx.status = TransactionStatus::Chargebacked;
-        self.held -= disputed_tx.amount.unwrap_or(Decimal::new(0, 0));
-        self.total -= disputed_tx.amount.unwrap_or(Decimal::new(0, 0));
+        let amount = Self::disputed_amount(disputed_tx, transaction_entity)?;
+        let currency = disputed_tx.currency.clone();
+
+        if let Some(transaction) = self.transactions.get_mut(&transaction_entity.tx) {
+            transaction.disputed -= amount;
+            transaction.charged_back += amount;
+            if transaction.disputed.is_zero() {
+                transaction.status = TransactionStatus::Chargebacked;
+            }
+        }
+        let balance = self.balance_mut(&currency);
+        balance.held -= amount;
+        balance.total -= amount;
         self.locked = true;
+        self.post(transaction_entity.tx, &currency, LedgerAccount::ClientHeld(self.client), LedgerAccount::ChargebackLoss, amount);
+
+        // The penalty is charged even if it takes the available funds below zero
+        let fee = self.fee(&currency, |fees| fees.chargeback_fee());
+        self.charge_fee(transaction_entity.tx, &currency, fee);
+
+        Ok(())
+    }
+
+    // Admin operation, so it is applied to locked accounts as well
+    fn handle_credit_limit(&mut self, transaction_entity: &TransactionEntity) -> Result<(), Box<dyn Error>> {
+        if self.config.credit_limits.is_none() {
+            return Err("Credit limits are disabled".into());
+        }
+
+        let limit = match transaction_entity.amount {
+            Some(limit) if !limit.is_sign_negative() => limit,
+            _ => return Err("Credit limit is invalid".into()),
+        };
+
+        self.balance_mut(transaction_entity.currency()).credit_limit = limit;
+
+        Ok(())
+    }
+
+    fn handle_transfer_debit(&mut self, transaction_entity: &TransactionEntity) -> Result<(), Box<dyn Error>> {
+        let amount = transaction_entity.amount.unwrap_or(Decimal::new(0, 0));
+        let currency = transaction_entity.currency();
+
+        if self.locked() {
+            return Err("Account is locked".into());
+        }
+
+        if amount.is_zero() || amount.is_sign_negative() || amount > self.balance(currency).spendable() {
+            return Err("Transfer amount is invalid".into());
+        }
+
+        self.balance_mut(currency).total -= amount;
+        self.post(transaction_entity.tx, currency, LedgerAccount::ClientAvailable(self.client), LedgerAccount::TransfersInTransit, amount);
 
         Ok(())
     }
+
+    fn handle_transfer_credit(&mut self, transaction_entity: &TransactionEntity) -> Result<(), Box<dyn Error>> {
+        let amount = transaction_entity.amount.unwrap_or(Decimal::new(0, 0));
+        let currency = transaction_entity.currency();
+
+        if self.locked() {
+            return Err("Destination account is locked".into());
+        }
+
+        if amount.is_zero() || amount.is_sign_negative() {
+            return Err("Transfer amount is invalid".into());
+        }
+
+        self.balance_mut(currency).total += amount;
+        self.post(transaction_entity.tx, currency, LedgerAccount::TransfersInTransit, LedgerAccount::ClientAvailable(self.client), amount);
+
+        Ok(())
+    }
+
+    // Returns the debited funds to the source, it can't fail as nothing else is applied in between
+    fn handle_transfer_rollback(&mut self, transaction_entity: &TransactionEntity) -> Result<(), Box<dyn Error>> {
+        let amount = transaction_entity.amount.unwrap_or(Decimal::new(0, 0));
+        let currency = transaction_entity.currency();
+
+        self.balance_mut(currency).total += amount;
+        self.post(transaction_entity.tx, currency, LedgerAccount::TransfersInTransit, LedgerAccount::ClientAvailable(self.client), amount);
+
+        Ok(())
+    }
+
+    // Amount of a resolve or chargeback, rows without an amount settle the whole open dispute
+    fn disputed_amount(disputed_tx: &Transaction, transaction_entity: &TransactionEntity) -> Result<Decimal, Box<dyn Error>> {
+        let amount = transaction_entity.amount.unwrap_or(disputed_tx.disputed);
+
+        if amount.is_zero() || amount.is_sign_negative() || amount > disputed_tx.disputed {
+            return Err("Amount is greater than the disputed part of the transaction".into());
+        }
+
+        Ok(amount)
+    }
+
+    fn fee<F>(&self, currency: &str, calculate: F) -> Decimal
+    where
+        F: Fn(&FeeSchedule) -> Decimal,
+    {
+        match self.config.fees.as_ref() {
+            Some(fees) => self.config.precision.for_currency(currency).round(calculate(fees)),
+            None => Decimal::new(0, 0),
+        }
+    }
+
+    fn charge_fee(&mut self, tx: u32, currency: &str, fee: Decimal) {
+        if fee.is_zero() {
+            return;
+        }
+
+        let balance = self.balance_mut(currency);
+        balance.total -= fee;
+        balance.fees += fee;
+        self.post(tx, currency, LedgerAccount::ClientAvailable(self.client), LedgerAccount::FeeIncome, fee);
+    }
+}
+
+// Declared in the order the legs of one transfer are applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TransferLeg {
+    Debit,
+    Credit,
+    Rollback,
 }
 
+pub type AccountQuery = Box<dyn FnOnce(&Account) + Send>;
+
+// Result of a transaction sent back to the engine, errors are strings so they can be sent between tasks
+pub type Reply = oneshot::Sender<Result<(), String>>;
+
 pub enum AccountWorkerMessage {
-    Transaction(TransactionEntity),
+    // Errors of messages without a reply channel are only logged
+    Transaction(TransactionEntity, Option<Reply>),
+    Transfer(TransactionEntity, TransferLeg, Option<Reply>),
+    // Runs after all the transactions sent before it, the query replies through its own channel
+    Query(u16, AccountQuery),
+    // Hands a new account to a worker serving several clients
+    Register(u16, Arc<RwLock<Account>>),
     Shutdown,
 }
 
+/// Processes the messages of its accounts one after another, one account per worker or several with sharding
 pub struct AccountWorker {
-    pub account: Arc<RwLock<Account>>,
-    receiver: mpsc::Receiver<AccountWorkerMessage>,
+    accounts: HashMap<u16, Arc<RwLock<Account>>>,
+    receiver: Option<mpsc::Receiver<AccountWorkerMessage>>,
 }
 
 impl AccountWorker {
-    pub fn new(receiver: mpsc::Receiver<AccountWorkerMessage>, account: Arc<RwLock<Account>>) -> Self {
+    pub fn new(receiver: mpsc::Receiver<AccountWorkerMessage>) -> Self {
         Self {
-            account: account,
-            receiver,
+            accounts: HashMap::new(),
+            receiver: Some(receiver),
         }
     }
 
+    /// Worker without a channel, its messages are passed to `handle` directly by the caller
+    pub fn inline() -> Self {
+        Self {
+            accounts: HashMap::new(),
+            receiver: None,
+        }
+    }
+
+    pub fn register(&mut self, client_id: u16, account: Arc<RwLock<Account>>) {
+        self.accounts.insert(client_id, account);
+    }
+
     pub async fn run(mut self) {
-        while let Some(msg) = self.receiver.recv().await {
-            match msg {
-                AccountWorkerMessage::Transaction(tx) => {
-                    let mut account = self.account.write().await;
-
-                    if let Err(e) = account.process_transaction(tx) {
-                        eprintln!("Error processing transaction: {}", e);
-                    }
+        let Some(mut receiver) = self.receiver.take() else {
+            return;
+        };
+
+        while let Some(msg) = receiver.recv().await {
+            if !self.handle(msg).await {
+                break;
+            }
+        }
+    }
+
+    /// Processes one message, returns false once the worker is asked to shut down
+    pub async fn handle(&mut self, msg: AccountWorkerMessage) -> bool {
+        match msg {
+            AccountWorkerMessage::Transaction(tx, reply) => {
+                let seq = tx.seq;
+                let result = match self.accounts.get(&tx.client) {
+                    Some(account) => Self::isolate(&mut *account.write().await, |account| account.process_transaction(tx)),
+                    None => Err(format!("Account {} not found", tx.client)),
+                };
+                Self::reply(reply, result, "Error processing transaction", seq);
+            }
+            AccountWorkerMessage::Transfer(tx, leg, reply) => {
+                let seq = tx.seq;
+                let client_id = match leg {
+                    TransferLeg::Credit => tx.to_client.unwrap_or(tx.client),
+                    TransferLeg::Debit | TransferLeg::Rollback => tx.client,
+                };
+                let result = match self.accounts.get(&client_id) {
+                    Some(account) => Self::isolate(&mut *account.write().await, |account| account.process_transfer(tx, leg)),
+                    None => Err(format!("Account {} not found", client_id)),
+                };
+                Self::reply(reply, result, "Error processing transfer", seq);
+            }
+            AccountWorkerMessage::Query(client_id, query) => {
+                // Dropping an unknown query closes its reply channel
+                if let Some(account) = self.accounts.get(&client_id) {
+                    query(&*account.read().await);
                 }
-                AccountWorkerMessage::Shutdown => break,
             }
+            AccountWorkerMessage::Register(client_id, account) => self.register(client_id, account),
+            AccountWorkerMessage::Shutdown => return false,
+        }
+
+        true
+    }
+
+    // A panic fails only the message which caused it, the account is recovered and the worker goes on
+    fn isolate<F>(account: &mut Account, process: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut Account) -> Result<(), Box<dyn Error>>,
+    {
+        match panic::catch_unwind(AssertUnwindSafe(|| process(account))) {
+            Ok(result) => result.map_err(|e| e.to_string()),
+            Err(payload) => {
+                let message = payload.downcast_ref::<String>().map(String::as_str)
+                    .or_else(|| payload.downcast_ref::<&str>().copied())
+                    .unwrap_or("unknown panic");
+
+                account.recover();
+                Err(format!("Worker of client {} panicked: {}", account.client(), message))
+            }
+        }
+    }
+
+    fn reply(reply: Option<Reply>, result: Result<(), String>, context: &str, seq: Option<u64>) {
+        match (reply, result, seq) {
+            (Some(reply), result, _) => {
+                let _ = reply.send(result);
+            }
+            (None, Err(e), Some(seq)) => eprintln!("{} #{}: {}", context, seq, e),
+            (None, Err(e), None) => eprintln!("{}: {}", context, e),
+            (None, Ok(()), _) => {}
         }
     }
 } 
@@ -253,8 +856,14 @@ impl AccountWorker {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rust_decimal::RoundingStrategy;
+    use std::time::Duration;
     use rust_decimal_macros::dec;
     use csv::WriterBuilder;
+    use crate::credit::CreditLimits;
+    use crate::ledger::merge_balances;
+    use crate::limits::{LimitExceeded, LimitRule, Limits, LimitsConfig, Window, WithdrawalWindow};
+    use crate::precision::{Precision, PrecisionConfig, PrecisionPolicy};
 
     fn serialize_to_string(account: &AccountEntity) -> String {
         let mut wtr = WriterBuilder::new().from_writer(vec![]);
@@ -329,6 +938,548 @@ mod tests {
         );
     }
 
+    fn entity(transaction_type: TransactionType, tx: u32, amount: Option<Decimal>) -> TransactionEntity {
+        TransactionEntity::new(transaction_type, 1, tx, amount)
+    }
+
+    #[test]
+    fn test_history_disabled_by_default() {
+        let mut account = Account::new(1);
+        account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(10.0)))).unwrap();
+
+        assert!(account.history().is_none());
+    }
+
+    #[test]
+    fn test_history_records_applied_operations() {
+        let config = Arc::new(EngineConfig { history: true, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+
+        account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(10.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Deposit, 2, Some(dec!(5.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Withdrawal, 3, Some(dec!(3.0)))).unwrap();
+        assert!(account.process_transaction(entity(TransactionType::Withdrawal, 4, Some(dec!(30.0)))).is_err());
+        account.process_transaction(entity(TransactionType::Dispute, 2, None)).unwrap();
+        account.process_transaction(entity(TransactionType::Chargeback, 2, None)).unwrap();
+
+        let history = account.history().unwrap();
+        assert_eq!(history.len(), 5);
+
+        assert_eq!(history[0].transaction_type, TransactionType::Deposit);
+        assert_eq!(history[0].total_before, dec!(0));
+        assert_eq!(history[0].total_after, dec!(10.0));
+        assert_eq!(history[0].status_before, None);
+        assert_eq!(history[0].status_after, Some(TransactionStatus::Normal));
+
+        assert_eq!(history[2].tx, 3);
+        assert_eq!(history[2].available_after, dec!(12.0));
+        assert_eq!(history[2].status_after, None);
+
+        assert_eq!(history[3].held_before, dec!(0));
+        assert_eq!(history[3].held_after, dec!(5.0));
+        assert_eq!(history[3].available_after, dec!(7.0));
+        assert_eq!(history[3].status_after, Some(TransactionStatus::Disputed));
+
+        assert_eq!(history[4].status_before, Some(TransactionStatus::Disputed));
+        assert_eq!(history[4].status_after, Some(TransactionStatus::Chargebacked));
+        assert_eq!(history[4].total_after, dec!(7.0));
+        assert!(history[4].locked);
+    }
+
+    #[test]
+    fn test_ledger_postings() {
+        let config = Arc::new(EngineConfig { double_entry: true, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+
+        account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(10.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Deposit, 2, Some(dec!(5.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Withdrawal, 3, Some(dec!(3.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Dispute, 1, None)).unwrap();
+        account.process_transaction(entity(TransactionType::Resolve, 1, None)).unwrap();
+        account.process_transaction(entity(TransactionType::Dispute, 2, None)).unwrap();
+        account.process_transaction(entity(TransactionType::Chargeback, 2, None)).unwrap();
+
+        let ledger = account.ledger().unwrap();
+        assert_eq!(ledger.postings().len(), 7);
+        assert_eq!(ledger.balance(LedgerAccount::Settlement, "USD"), dec!(-12.0));
+        assert_eq!(ledger.balance(LedgerAccount::ChargebackLoss, "USD"), dec!(5.0));
+        assert_eq!(ledger.balance(LedgerAccount::ClientHeld(1), "USD"), dec!(0));
+        assert_eq!(ledger.balance(LedgerAccount::ClientAvailable(1), "USD"), dec!(7.0));
+        assert!(account.reconcile_ledger().is_ok());
+    }
+
+    #[test]
+    fn test_reconcile_ledger_detects_direct_changes() {
+        let config = Arc::new(EngineConfig { double_entry: true, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+
+        account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(10.0)))).unwrap();
+        account.set_total(dec!(20.0));
+
+        assert!(account.reconcile_ledger().is_err());
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let mut account = Account::new(1);
+        account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(10.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Dispute, 1, None)).unwrap();
+        assert!(account.check_invariants().is_ok());
+
+        account.set_held(dec!(-1.0));
+        assert!(account.check_invariants().is_err());
+
+        account.set_held(dec!(5.0));
+        assert!(account.check_invariants().is_err());
+    }
+
+    #[test]
+    fn test_check_invariants_total_below_held() {
+        let mut account = Account::new(1);
+        account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(10.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Dispute, 1, Some(dec!(4.0)))).unwrap();
+
+        account.set_total(dec!(3.0));
+        assert_eq!(account.check_invariants().unwrap_err().to_string(), "total 3.0 USD is less than held 4.0");
+
+        // The same balances are fine on a credit line, but not with a zero limit
+        let limits = CreditLimits::from([
+            (1, HashMap::from([("USD".to_string(), dec!(5.0))])),
+            (2, HashMap::from([("USD".to_string(), dec!(0))])),
+        ]);
+        let config = Arc::new(EngineConfig { credit_limits: Some(limits), ..Default::default() });
+        for (client, valid) in [(1, true), (2, false)] {
+            let mut account = Account::with_config(client, config.clone());
+            account.process_transaction(TransactionEntity { client, ..entity(TransactionType::Deposit, 1, Some(dec!(10.0))) }).unwrap();
+            account.process_transaction(TransactionEntity { client, ..entity(TransactionType::Dispute, 1, Some(dec!(4.0))) }).unwrap();
+            account.set_total(dec!(3.0));
+            assert_eq!(account.check_invariants().is_ok(), valid);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Invariant violated for client 1 after tx 2")]
+    fn test_invariant_violation_panics() {
+        let config = Arc::new(EngineConfig { check_invariants: true, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+
+        account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(10.0)))).unwrap();
+        account.set_held(dec!(3.0));
+        let _ = account.process_transaction(entity(TransactionType::Deposit, 2, Some(dec!(1.0))));
+    }
+
+    #[test]
+    fn test_balances_per_currency() {
+        let config = Arc::new(EngineConfig { check_invariants: true, double_entry: true, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+
+        let mut eur_deposit = entity(TransactionType::Deposit, 1, Some(dec!(10.0)));
+        eur_deposit.currency = Some("EUR".to_string());
+        account.process_transaction(eur_deposit).unwrap();
+        account.process_transaction(entity(TransactionType::Deposit, 2, Some(dec!(5.0)))).unwrap();
+
+        // No EUR funds are available for a withdrawal in the default currency
+        assert!(account.process_transaction(entity(TransactionType::Withdrawal, 3, Some(dec!(6.0)))).is_err());
+
+        // Dispute rows have no currency, funds are held in the currency of the disputed deposit
+        account.process_transaction(entity(TransactionType::Dispute, 1, None)).unwrap();
+
+        assert_eq!(account.balance("EUR"), Balance { held: dec!(10.0), total: dec!(10.0), ..Default::default() });
+        assert_eq!(account.balance("USD"), Balance { total: dec!(5.0), ..Default::default() });
+        assert_eq!(account.available(), dec!(5.0));
+
+        let entities = account.entities();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0].currency, Some("EUR".to_string()));
+        assert_eq!(entities[0].available, dec!(0));
+        assert_eq!(entities[1].currency, Some("USD".to_string()));
+        assert_eq!(entities[1].total, dec!(5.0));
+    }
+
+    #[test]
+    fn test_entities_without_balances() {
+        let account = Account::new(3);
+        let entities = account.entities();
+
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].currency, Some(DEFAULT_CURRENCY.to_string()));
+        assert_eq!(entities[0].total, dec!(0));
+    }
+
+    #[test]
+    fn test_precision_per_currency() {
+        let precision = PrecisionConfig::default()
+            .with_currency("JPY", Precision::new(0, RoundingStrategy::ToZero))
+            .with_currency("BTC", Precision::new(8, RoundingStrategy::ToZero));
+        let config = Arc::new(EngineConfig { precision, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+
+        let mut jpy_deposit = entity(TransactionType::Deposit, 1, Some(dec!(1000.75)));
+        jpy_deposit.currency = Some("JPY".to_string());
+        account.process_transaction(jpy_deposit).unwrap();
+
+        let mut btc_deposit = entity(TransactionType::Deposit, 2, Some(dec!(0.123456789)));
+        btc_deposit.currency = Some("BTC".to_string());
+        account.process_transaction(btc_deposit).unwrap();
+
+        account.process_transaction(entity(TransactionType::Deposit, 3, Some(dec!(1.23456789)))).unwrap();
+
+        assert_eq!(account.balance("JPY").total, dec!(1000));
+        assert_eq!(account.balance("BTC").total, dec!(0.12345678));
+        assert_eq!(account.balance("USD").total, dec!(1.2345));
+    }
+
+    #[test]
+    fn test_precision_reject_policy() {
+        let precision = PrecisionConfig { policy: PrecisionPolicy::Reject, ..Default::default() };
+        let config = Arc::new(EngineConfig { precision, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+
+        assert!(account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(1.23456)))).is_err());
+        account.process_transaction(entity(TransactionType::Deposit, 2, Some(dec!(1.2345)))).unwrap();
+
+        assert_eq!(account.total(), dec!(1.2345));
+    }
+
+    #[test]
+    fn test_fees() {
+        let fees = FeeSchedule {
+            withdrawal_fixed: dec!(1.0),
+            withdrawal_percent: dec!(0.5),
+            chargeback_penalty: dec!(20.0),
+        };
+        let config = Arc::new(EngineConfig { fees: Some(fees), check_invariants: true, double_entry: true, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+
+        account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(100.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Deposit, 2, Some(dec!(50.0)))).unwrap();
+
+        // 99.5 + 1.4975 of fees does not fit into 150 - 50 disputed
+        account.process_transaction(entity(TransactionType::Dispute, 2, None)).unwrap();
+        assert!(account.process_transaction(entity(TransactionType::Withdrawal, 3, Some(dec!(99.5)))).is_err());
+
+        account.process_transaction(entity(TransactionType::Withdrawal, 4, Some(dec!(90.0)))).unwrap();
+        assert_eq!(account.available(), dec!(8.55));
+        assert_eq!(account.balance("USD").fees, dec!(1.45));
+
+        account.process_transaction(entity(TransactionType::Chargeback, 2, None)).unwrap();
+        assert_eq!(account.available(), dec!(-11.45));
+        assert_eq!(account.total(), dec!(-11.45));
+        assert_eq!(account.balance("USD").fees, dec!(21.45));
+
+        let ledger = account.ledger().unwrap();
+        assert_eq!(ledger.balance(LedgerAccount::FeeIncome, "USD"), dec!(21.45));
+
+        let entity = AccountEntity::from(&account);
+        assert_eq!(
+            serialize_to_string(&entity),
+            "client,available,held,total,fees,locked\n1,-11.45,0.0,-11.45,21.45,true\n"
+        );
+    }
+
+    #[test]
+    fn test_transfer_legs() {
+        let config = Arc::new(EngineConfig { double_entry: true, check_invariants: true, ..Default::default() });
+        let mut source = Account::with_config(1, config.clone());
+        let mut destination = Account::with_config(2, config);
+
+        source.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(10.0)))).unwrap();
+
+        let mut transfer = entity(TransactionType::Transfer, 2, Some(dec!(4.0)));
+        transfer.to_client = Some(2);
+
+        assert!(source.process_transaction(transfer.clone()).is_err());
+        source.process_transfer(transfer.clone(), TransferLeg::Debit).unwrap();
+        destination.process_transfer(transfer.clone(), TransferLeg::Credit).unwrap();
+
+        assert_eq!(source.available(), dec!(6.0));
+        assert_eq!(destination.available(), dec!(4.0));
+
+        let ledger = merge_balances([source.ledger().unwrap(), destination.ledger().unwrap()]);
+        assert_eq!(ledger[&(LedgerAccount::TransfersInTransit, "USD".to_string())], dec!(0));
+
+        transfer.amount = Some(dec!(7.0));
+        assert!(source.process_transfer(transfer.clone(), TransferLeg::Debit).is_err());
+
+        transfer.amount = Some(dec!(6.0));
+        source.process_transfer(transfer.clone(), TransferLeg::Debit).unwrap();
+        destination.lock();
+        assert!(destination.process_transfer(transfer.clone(), TransferLeg::Credit).is_err());
+        source.process_transfer(transfer, TransferLeg::Rollback).unwrap();
+
+        assert_eq!(source.available(), dec!(6.0));
+        assert_eq!(destination.available(), dec!(4.0));
+    }
+
+    #[test]
+    fn test_credit_limit() {
+        let limits = CreditLimits::from([(1, HashMap::from([("USD".to_string(), dec!(50.0))]))]);
+        let config = Arc::new(EngineConfig { credit_limits: Some(limits), check_invariants: true, double_entry: true, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+
+        account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(20.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Withdrawal, 2, Some(dec!(60.0)))).unwrap();
+        assert!(account.process_transaction(entity(TransactionType::Withdrawal, 3, Some(dec!(10.1)))).is_err());
+
+        assert_eq!(account.available(), dec!(-40.0));
+        assert_eq!(account.balance("USD").remaining_credit(), dec!(10.0));
+
+        // Admin transaction raises the limit
+        account.process_transaction(entity(TransactionType::CreditLimit, 4, Some(dec!(100.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Withdrawal, 5, Some(dec!(50.0)))).unwrap();
+        assert!(account.process_transaction(entity(TransactionType::CreditLimit, 6, Some(dec!(-1.0)))).is_err());
+
+        let entity = AccountEntity::from(&account);
+        assert_eq!(
+            serialize_to_string(&entity),
+            "client,available,held,total,credit_limit,remaining_credit,locked\n1,-90.0,0,-90.0,100.0,10.0,false\n"
+        );
+    }
+
+    #[test]
+    fn test_credit_limit_disabled() {
+        let mut account = Account::new(1);
+
+        assert!(account.process_transaction(entity(TransactionType::CreditLimit, 1, Some(dec!(100.0)))).is_err());
+        assert!(account.process_transaction(entity(TransactionType::Withdrawal, 2, Some(dec!(1.0)))).is_err());
+    }
+
+    #[test]
+    fn test_remaining_credit() {
+        let balance = Balance { total: dec!(-30), credit_limit: dec!(20), ..Default::default() };
+        assert_eq!(balance.remaining_credit(), dec!(0));
+
+        let balance = Balance { total: dec!(30), credit_limit: dec!(20), ..Default::default() };
+        assert_eq!(balance.remaining_credit(), dec!(20));
+        assert_eq!(balance.spendable(), dec!(50));
+    }
+
+    #[test]
+    fn test_limits() {
+        let window = WithdrawalWindow { max_total: dec!(100), window: Window::Transactions(2) };
+        let limits = LimitsConfig {
+            global: HashMap::from([(DEFAULT_CURRENCY.to_string(), Limits { max_deposit: Some(dec!(1000)), max_withdrawal: Some(dec!(80)), withdrawal_window: Some(window) })]),
+            ..Default::default()
+        };
+        let config = Arc::new(EngineConfig { limits, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+
+        let error = account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(1000.5)))).unwrap_err();
+        assert_eq!(error.downcast_ref::<LimitExceeded>().unwrap().rule, LimitRule::MaxDeposit);
+        account.process_transaction(entity(TransactionType::Deposit, 2, Some(dec!(1000)))).unwrap();
+
+        let error = account.process_transaction(entity(TransactionType::Withdrawal, 3, Some(dec!(81)))).unwrap_err();
+        assert_eq!(error.downcast_ref::<LimitExceeded>().unwrap().rule, LimitRule::MaxWithdrawal);
+
+        account.process_transaction(entity(TransactionType::Withdrawal, 4, Some(dec!(60)))).unwrap();
+        let error = account.process_transaction(entity(TransactionType::Withdrawal, 5, Some(dec!(50)))).unwrap_err();
+        assert_eq!(error.to_string(), "LimitExceeded: withdrawal window limit is 100, attempted 110");
+
+        account.process_transaction(entity(TransactionType::Deposit, 6, Some(dec!(1)))).unwrap();
+        account.process_transaction(entity(TransactionType::Withdrawal, 7, Some(dec!(50)))).unwrap();
+
+        assert_eq!(account.total(), dec!(891));
+    }
+
+    fn timed(transaction_type: TransactionType, tx: u32, amount: Option<Decimal>, timestamp: Option<u64>) -> TransactionEntity {
+        TransactionEntity {
+            timestamp,
+            ..entity(transaction_type, tx, amount)
+        }
+    }
+
+    #[test]
+    fn test_timestamps_never_go_backwards() {
+        let mut account = Account::new(1);
+
+        account.process_transaction(timed(TransactionType::Deposit, 1, Some(dec!(10.0)), Some(2000))).unwrap();
+        assert!(account.process_transaction(timed(TransactionType::Deposit, 2, Some(dec!(10.0)), Some(1999))).is_err());
+        account.process_transaction(timed(TransactionType::Deposit, 3, Some(dec!(10.0)), None)).unwrap();
+        account.process_transaction(timed(TransactionType::Deposit, 4, Some(dec!(10.0)), Some(2000))).unwrap();
+
+        assert_eq!(account.total(), dec!(30.0));
+    }
+
+    #[test]
+    fn test_sequence_numbers_never_go_backwards() {
+        let config = Arc::new(EngineConfig { history: true, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+        let sequenced = |tx, seq| TransactionEntity { seq, ..entity(TransactionType::Deposit, tx, Some(dec!(1.0))) };
+
+        account.process_transaction(sequenced(1, Some(5))).unwrap();
+        let err = account.process_transaction(sequenced(2, Some(4))).unwrap_err();
+        assert_eq!(err.to_string(), "Sequence number 4 is earlier than the previous one 5");
+        account.process_transaction(sequenced(3, None)).unwrap();
+        account.process_transaction(sequenced(4, Some(9))).unwrap();
+
+        let seqs: Vec<Option<u64>> = account.history().unwrap().iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, vec![Some(5), None, Some(9)]);
+    }
+
+    // Runs the messages through a worker, the account is corrupted first so that the invariant check panics
+    async fn run_corrupted_worker(policy: SupervisionPolicy) -> (Vec<Result<(), String>>, Account) {
+        let config = Arc::new(EngineConfig { check_invariants: true, supervision: policy, ..Default::default() });
+        let account = Arc::new(RwLock::new(Account::with_config(1, config)));
+        let (sender, receiver) = mpsc::channel(10);
+        let mut worker = AccountWorker::new(receiver);
+        worker.register(1, account.clone());
+        let worker = tokio::spawn(worker.run());
+
+        let mut replies = Vec::new();
+        let mut send = |transaction_entity| {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            replies.push(reply_rx);
+            AccountWorkerMessage::Transaction(transaction_entity, Some(reply_tx))
+        };
+
+        sender.send(send(entity(TransactionType::Deposit, 1, Some(dec!(10.0))))).await.unwrap();
+        // Waits for the deposit, then breaks the balance behind the worker's back
+        while account.read().await.total() != dec!(10.0) {
+            tokio::task::yield_now().await;
+        }
+        account.write().await.set_held(dec!(3.0));
+
+        sender.send(send(entity(TransactionType::Deposit, 2, Some(dec!(5.0))))).await.unwrap();
+        sender.send(send(entity(TransactionType::Deposit, 3, Some(dec!(1.0))))).await.unwrap();
+        sender.send(AccountWorkerMessage::Shutdown).await.unwrap();
+        worker.await.unwrap();
+
+        let mut results = Vec::new();
+        for reply in replies {
+            results.push(reply.await.unwrap());
+        }
+
+        let account = Arc::try_unwrap(account).ok().unwrap().into_inner();
+        (results, account)
+    }
+
+    #[tokio::test]
+    async fn test_worker_panic_faults_account() {
+        let (results, account) = run_corrupted_worker(SupervisionPolicy::Fault).await;
+
+        assert!(results[0].is_ok());
+        assert!(results[1].as_ref().unwrap_err().contains("panicked: Invariant violated for client 1 after tx 2"));
+        assert_eq!(results[2], Err("Account is faulted".to_string()));
+        assert!(account.is_faulted());
+    }
+
+    #[tokio::test]
+    async fn test_worker_panic_rebuilds_account() {
+        let (results, account) = run_corrupted_worker(SupervisionPolicy::Rebuild).await;
+
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
+        assert!(!account.is_faulted());
+        // The corrupted held funds and the panicking deposit are gone, the journaled deposit is replayed
+        assert_eq!((account.available(), account.held(), account.total()), (dec!(11.0), dec!(0), dec!(11.0)));
+    }
+
+    #[test]
+    fn test_entity_after() {
+        let config = Arc::new(EngineConfig { history: true, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+        let with_seq = |seq: u64, transaction: TransactionEntity| TransactionEntity { seq: Some(seq), ..transaction };
+
+        account.process_transaction(with_seq(1, entity(TransactionType::Deposit, 1, Some(dec!(100.0))))).unwrap();
+        account.process_transaction(with_seq(2, entity(TransactionType::Withdrawal, 2, Some(dec!(30.0))))).unwrap();
+        account.process_transaction(with_seq(3, entity(TransactionType::Deposit, 3, Some(dec!(50.0))))).unwrap();
+        account.process_transaction(with_seq(4, entity(TransactionType::Dispute, 3, None))).unwrap();
+        account.process_transaction(with_seq(5, entity(TransactionType::Withdrawal, 4, Some(dec!(80.0))))).unwrap_err();
+        account.process_transaction(with_seq(6, entity(TransactionType::Resolve, 3, None))).unwrap();
+
+        let balances = |seq: u64| {
+            let entity = account.entity_after(seq).unwrap();
+            (entity.available, entity.held, entity.total)
+        };
+        assert_eq!(balances(1), (dec!(100.0), dec!(0), dec!(100.0)));
+        assert_eq!(balances(2), (dec!(70.0), dec!(0), dec!(70.0)));
+        assert_eq!(balances(3), (dec!(120.0), dec!(0), dec!(120.0)));
+        // The dispute and the resolve of the deposit 3 each have their own balances
+        assert_eq!(balances(4), (dec!(70.0), dec!(50.0), dec!(120.0)));
+        assert_eq!(balances(6), (dec!(120.0), dec!(0), dec!(120.0)));
+        // The withdrawal failed as the funds were held, the balance is the one after the dispute
+        assert_eq!(balances(5), balances(4));
+        assert_eq!(balances(100), balances(6));
+
+        assert!(account.entity_after(0).is_err());
+        assert!(Account::new(1).entity_after(1).is_err());
+    }
+
+    #[test]
+    fn test_partial_disputes() {
+        let config = Arc::new(EngineConfig { check_invariants: true, ..Default::default() });
+        let mut account = Account::with_config(1, config);
+
+        account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(100.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Dispute, 1, Some(dec!(30.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Dispute, 1, Some(dec!(50.0)))).unwrap();
+        assert_eq!(account.held(), dec!(80.0));
+
+        // Only 20 is left undisputed
+        assert!(account.process_transaction(entity(TransactionType::Dispute, 1, Some(dec!(30.0)))).is_err());
+        assert!(account.process_transaction(entity(TransactionType::Resolve, 1, Some(dec!(90.0)))).is_err());
+
+        account.process_transaction(entity(TransactionType::Resolve, 1, Some(dec!(30.0)))).unwrap();
+        assert_eq!(account.transaction_status(1), Some(TransactionStatus::Disputed));
+        account.process_transaction(entity(TransactionType::Dispute, 1, None)).unwrap();
+        assert_eq!(account.held(), dec!(70.0));
+        assert!(account.process_transaction(entity(TransactionType::Dispute, 1, Some(dec!(0.01)))).is_err());
+
+        account.process_transaction(entity(TransactionType::Chargeback, 1, Some(dec!(50.0)))).unwrap();
+        assert_eq!(account.held(), dec!(20.0));
+        assert_eq!(account.total(), dec!(50.0));
+        assert_eq!(account.available(), dec!(30.0));
+        assert!(account.locked());
+        assert_eq!(account.transaction_status(1), Some(TransactionStatus::Disputed));
+
+        // The rest of the dispute is still settled on the locked account
+        assert!(account.process_transaction(entity(TransactionType::Dispute, 1, None)).is_err());
+        account.process_transaction(entity(TransactionType::Resolve, 1, None)).unwrap();
+        assert_eq!(account.held(), dec!(0));
+        assert_eq!(account.available(), dec!(50.0));
+        assert_eq!(account.total(), dec!(50.0));
+        assert_eq!(account.transaction_status(1), Some(TransactionStatus::Chargebacked));
+        assert!(account.process_transaction(entity(TransactionType::Resolve, 1, None)).is_err());
+    }
+
+    #[test]
+    fn test_resolve_without_amount_settles_whole_dispute() {
+        let mut account = Account::new(1);
+
+        account.process_transaction(entity(TransactionType::Deposit, 1, Some(dec!(100.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Dispute, 1, Some(dec!(30.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Dispute, 1, Some(dec!(20.0)))).unwrap();
+        account.process_transaction(entity(TransactionType::Resolve, 1, None)).unwrap();
+
+        assert_eq!(account.held(), dec!(0));
+        assert_eq!(account.transaction_status(1), Some(TransactionStatus::Resolved));
+        assert!(account.process_transaction(entity(TransactionType::Dispute, 1, Some(dec!(-1.0)))).is_err());
+        account.process_transaction(entity(TransactionType::Dispute, 1, None)).unwrap();
+        assert_eq!(account.held(), dec!(50.0));
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        let day = 24 * 60 * 60;
+        let config = Arc::new(EngineConfig { dispute_window: Some(Duration::from_secs(120 * day)), ..Default::default() });
+        let mut account = Account::with_config(1, config);
+
+        account.process_transaction(timed(TransactionType::Deposit, 1, Some(dec!(10.0)), Some(0))).unwrap();
+        account.process_transaction(timed(TransactionType::Deposit, 2, Some(dec!(10.0)), Some(10 * day))).unwrap();
+        account.process_transaction(timed(TransactionType::Deposit, 3, Some(dec!(10.0)), None)).unwrap();
+
+        assert!(account.process_transaction(timed(TransactionType::Dispute, 1, None, Some(121 * day))).is_err());
+        account.process_transaction(timed(TransactionType::Dispute, 2, None, Some(121 * day))).unwrap();
+
+        // The latest timestamp of the client is used for rows without it, deposits without it can always be disputed
+        account.process_transaction(timed(TransactionType::Resolve, 2, None, None)).unwrap();
+        account.process_transaction(timed(TransactionType::Dispute, 3, None, Some(1000 * day))).unwrap();
+        assert!(account.process_transaction(timed(TransactionType::Dispute, 1, None, None)).is_err());
+
+        assert_eq!(account.held(), dec!(10.0));
+    }
+
     #[test]
     fn test_account_available_calculation() {
         let mut account = Account::new(1);
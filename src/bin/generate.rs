@@ -0,0 +1,48 @@
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use payment_engine::config::InputFormat;
+use payment_engine::generator::{Generator, GeneratorConfig};
+
+const USAGE: &str = "Usage: cargo run --release --bin generate -- [--rows <n>] [--clients <n>] [--seed <n>] [--format csv|jsonl] \
+[--weights <deposit,withdrawal,dispute,resolve,chargeback,transfer>] [--deposit-amounts <distribution>] \
+[--withdrawal-amounts <distribution>] [--partial-disputes <ratio>] [--invalid <ratio>] [--output <file>]
+Distributions: uniform:<min>:<max> or exponential:<mean>";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+
+    let mut config = GeneratorConfig::default();
+    let mut format = InputFormat::Csv;
+    let mut output_path = None;
+    let mut options = args[1..].iter();
+    while let Some(arg) = options.next() {
+        let mut value = || options.next().ok_or(USAGE);
+
+        match arg.as_str() {
+            "--rows" => config.rows = value()?.parse()?,
+            "--clients" => config.clients = value()?.parse()?,
+            "--seed" => config.seed = value()?.parse()?,
+            "--format" => format = match value()?.as_str() {
+                "csv" => InputFormat::Csv,
+                "jsonl" => InputFormat::Jsonl,
+                other => return Err(format!("Unknown format {}\n{}", other, USAGE).into()),
+            },
+            "--weights" => config.weights = value()?.parse()?,
+            "--deposit-amounts" => config.deposit_amounts = value()?.parse()?,
+            "--withdrawal-amounts" => config.withdrawal_amounts = value()?.parse()?,
+            "--partial-disputes" => config.partial_dispute_ratio = value()?.parse()?,
+            "--invalid" => config.invalid_ratio = value()?.parse()?,
+            "--output" => output_path = Some(value()?.clone()),
+            _ => return Err(format!("Unknown option {}\n{}", arg, USAGE).into()),
+        }
+    }
+
+    let output: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    Generator::new(config).write(format, BufWriter::new(output))
+}
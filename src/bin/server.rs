@@ -0,0 +1,65 @@
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+use payment_engine::config::EngineConfig;
+use payment_engine::ingest::serve_ingest_until;
+use payment_engine::payment_engine::PaymentEngine;
+use payment_engine::server::{serve_until, ServerState};
+use payment_engine::shutdown::listen_for_signals;
+use std::io;
+use std::process;
+use tokio::net::TcpListener;
+
+const USAGE: &str = "Usage: cargo run --bin server -- [--addr <host:port>] [--ingest-addr <host:port>] [--snapshot <file>] [--history] [--check-invariants]";
+const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+
+    let mut addr = DEFAULT_ADDR.to_string();
+    let mut ingest_addr = None;
+    let mut snapshot = None;
+    let mut config = EngineConfig::default();
+    let mut options = args[1..].iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--addr" => addr = options.next().ok_or(USAGE)?.clone(),
+            "--ingest-addr" => ingest_addr = Some(options.next().ok_or(USAGE)?.clone()),
+            "--snapshot" => snapshot = Some(PathBuf::from(options.next().ok_or(USAGE)?)),
+            "--history" => config.history = true,
+            "--check-invariants" => config.check_invariants = true,
+            _ => return Err(format!("Unknown option {}\n{}", arg, USAGE).into()),
+        }
+    }
+
+    let listener = TcpListener::bind(&addr).await?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+
+    let state = ServerState::new(PaymentEngine::with_config(config), snapshot);
+    let shutdown = listen_for_signals()?;
+
+    match ingest_addr {
+        Some(ingest_addr) => {
+            let ingest_listener = TcpListener::bind(&ingest_addr).await?;
+            eprintln!("Ingesting on {}", ingest_listener.local_addr()?);
+
+            tokio::try_join!(
+                serve_until(listener, state.clone(), shutdown.clone()),
+                serve_ingest_until(ingest_listener, state.engine(), shutdown.clone()),
+            )?;
+        }
+        None => serve_until(listener, state.clone(), shutdown.clone()).await?,
+    }
+
+    state.shutdown(io::stdout()).await?;
+
+    // The servers only return on their own after a signal
+    let signal = *shutdown.borrow();
+    if let Some(signal) = signal {
+        eprintln!("Stopped by {}, the accounts are written", signal.name());
+        process::exit(signal.exit_code());
+    }
+
+    Ok(())
+}
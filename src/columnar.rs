@@ -0,0 +1,265 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow_array::builder::{BooleanBuilder, Decimal128Builder, StringBuilder, UInt16Builder, UInt32Builder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::account::AccountEntity;
+use crate::history::HistoryEntry;
+
+/// Largest precision of `Decimal128`
+const ARROW_DECIMAL_PRECISION: u8 = 38;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnarFormat {
+    Parquet,
+    ArrowIpc,
+}
+
+impl ColumnarFormat {
+    /// Format of a file by its extension, Arrow IPC for `.arrow` and `.ipc`, Parquet otherwise
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|extension| extension.to_str()) {
+            Some("arrow" | "ipc") => ColumnarFormat::ArrowIpc,
+            _ => ColumnarFormat::Parquet,
+        }
+    }
+}
+
+/// Files the accounts and the history are written to when the run stops, each in the format of its extension
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColumnarOutput {
+    pub accounts: Option<PathBuf>,
+    /// Requires history to be enabled in the engine config
+    pub history: Option<PathBuf>,
+}
+
+fn decimal_field(name: &str, scale: u32, nullable: bool) -> Field {
+    Field::new(name, DataType::Decimal128(ARROW_DECIMAL_PRECISION, scale as i8), nullable)
+}
+
+fn decimal_builder(capacity: usize, scale: u32) -> Result<Decimal128Builder, Box<dyn Error>> {
+    Ok(Decimal128Builder::with_capacity(capacity).with_precision_and_scale(ARROW_DECIMAL_PRECISION, scale as i8)?)
+}
+
+// Units of the smallest fraction, amounts with more fractional digits fail instead of being rounded
+fn decimal_units(value: Decimal, scale: u32) -> Result<i128, Box<dyn Error>> {
+    let mut scaled = value;
+    scaled.rescale(scale);
+    if scaled != value || scaled.scale() != scale {
+        return Err(format!("{} does not fit into {} fractional digits", value, scale).into());
+    }
+
+    Ok(scaled.mantissa())
+}
+
+fn append_decimal(builder: &mut Decimal128Builder, value: Option<Decimal>, scale: u32) -> Result<(), Box<dyn Error>> {
+    builder.append_option(value.map(|value| decimal_units(value, scale)).transpose()?);
+    Ok(())
+}
+
+// Same names as in the CSV and JSON outputs
+fn name<T: Serialize>(value: &T) -> Result<String, Box<dyn Error>> {
+    match serde_json::to_value(value)? {
+        serde_json::Value::String(name) => Ok(name),
+        value => Err(format!("{} is not a name", value).into()),
+    }
+}
+
+/// Accounts with the columns of the CSV output, the optional ones are always present and null when not used.
+/// The amounts are rounded to the precision of their currency like in the CSV output and stored with `scale`
+/// fractional digits, `PrecisionConfig::max_scale` fits all of them.
+pub fn accounts_batch(accounts: &[AccountEntity], scale: u32) -> Result<RecordBatch, Box<dyn Error>> {
+    let schema = Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("currency", DataType::Utf8, true),
+        decimal_field("available", scale, false),
+        decimal_field("held", scale, false),
+        decimal_field("total", scale, false),
+        decimal_field("fees", scale, true),
+        decimal_field("credit_limit", scale, true),
+        decimal_field("remaining_credit", scale, true),
+        Field::new("locked", DataType::Boolean, false),
+    ]);
+
+    let mut client = UInt16Builder::with_capacity(accounts.len());
+    let mut currency = StringBuilder::new();
+    let mut decimals = (0..6).map(|_| decimal_builder(accounts.len(), scale)).collect::<Result<Vec<_>, _>>()?;
+    let mut locked = BooleanBuilder::with_capacity(accounts.len());
+
+    for account in accounts {
+        client.append_value(account.client);
+        currency.append_option(account.currency.as_deref());
+        let values = [
+            Some(account.available),
+            Some(account.held),
+            Some(account.total),
+            account.fees,
+            account.credit_limit,
+            account.remaining_credit,
+        ];
+        for (builder, value) in decimals.iter_mut().zip(values) {
+            append_decimal(builder, value.map(|value| account.precision.round(value)), scale)?;
+        }
+        locked.append_value(account.locked);
+    }
+
+    let mut columns: Vec<ArrayRef> = vec![Arc::new(client.finish()), Arc::new(currency.finish())];
+    columns.extend(decimals.iter_mut().map(|builder| Arc::new(builder.finish()) as ArrayRef));
+    columns.push(Arc::new(locked.finish()));
+
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+/// History entries with the columns of the CSV statement, the amounts are stored like by `accounts_batch`
+pub fn history_batch(entries: &[HistoryEntry], scale: u32) -> Result<RecordBatch, Box<dyn Error>> {
+    let schema = Schema::new(vec![
+        Field::new("seq", DataType::UInt64, true),
+        Field::new("client", DataType::UInt16, false),
+        Field::new("tx", DataType::UInt32, false),
+        Field::new("type", DataType::Utf8, false),
+        Field::new("currency", DataType::Utf8, false),
+        decimal_field("available_before", scale, false),
+        decimal_field("held_before", scale, false),
+        decimal_field("total_before", scale, false),
+        decimal_field("available_after", scale, false),
+        decimal_field("held_after", scale, false),
+        decimal_field("total_after", scale, false),
+        Field::new("status_before", DataType::Utf8, true),
+        Field::new("status_after", DataType::Utf8, true),
+        Field::new("locked", DataType::Boolean, false),
+    ]);
+
+    let mut seq = UInt64Builder::with_capacity(entries.len());
+    let mut client = UInt16Builder::with_capacity(entries.len());
+    let mut tx = UInt32Builder::with_capacity(entries.len());
+    let mut transaction_type = StringBuilder::new();
+    let mut currency = StringBuilder::new();
+    let mut decimals = (0..6).map(|_| decimal_builder(entries.len(), scale)).collect::<Result<Vec<_>, _>>()?;
+    let mut status_before = StringBuilder::new();
+    let mut status_after = StringBuilder::new();
+    let mut locked = BooleanBuilder::with_capacity(entries.len());
+
+    for entry in entries {
+        seq.append_option(entry.seq);
+        client.append_value(entry.client);
+        tx.append_value(entry.tx);
+        transaction_type.append_value(name(&entry.transaction_type)?);
+        currency.append_value(&entry.currency);
+        let values = [
+            entry.available_before,
+            entry.held_before,
+            entry.total_before,
+            entry.available_after,
+            entry.held_after,
+            entry.total_after,
+        ];
+        for (builder, value) in decimals.iter_mut().zip(values) {
+            append_decimal(builder, Some(entry.precision.round(value)), scale)?;
+        }
+        status_before.append_option(entry.status_before.as_ref().map(name).transpose()?);
+        status_after.append_option(entry.status_after.as_ref().map(name).transpose()?);
+        locked.append_value(entry.locked);
+    }
+
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(seq.finish()),
+        Arc::new(client.finish()),
+        Arc::new(tx.finish()),
+        Arc::new(transaction_type.finish()),
+        Arc::new(currency.finish()),
+    ];
+    columns.extend(decimals.iter_mut().map(|builder| Arc::new(builder.finish()) as ArrayRef));
+    columns.push(Arc::new(status_before.finish()));
+    columns.push(Arc::new(status_after.finish()));
+    columns.push(Arc::new(locked.finish()));
+
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+/// Writes the batch as a Parquet file (Snappy compressed) or as an Arrow IPC file
+pub fn write_batch<W: Write + Send>(batch: &RecordBatch, format: ColumnarFormat, output: W) -> Result<(), Box<dyn Error>> {
+    match format {
+        ColumnarFormat::Parquet => {
+            let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+            let mut writer = ArrowWriter::try_new(output, batch.schema(), Some(properties))?;
+            writer.write(batch)?;
+            writer.close()?;
+        }
+        ColumnarFormat::ArrowIpc => {
+            let mut writer = FileWriter::try_new(output, &batch.schema())?;
+            writer.write(batch)?;
+            writer.finish()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates the file and writes the batch in the format of its extension
+pub fn write_file<P: AsRef<Path>>(batch: &RecordBatch, path: P) -> Result<(), Box<dyn Error>> {
+    let format = ColumnarFormat::from_path(&path);
+    write_batch(batch, format, File::create(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::precision::Precision;
+    use arrow_array::{Array, Decimal128Array, StringArray};
+    use rust_decimal::RoundingStrategy;
+    use rust_decimal_macros::dec;
+
+    fn account(client: u16, available: Decimal) -> AccountEntity {
+        AccountEntity {
+            client,
+            currency: None,
+            available,
+            held: dec!(0),
+            total: available,
+            fees: None,
+            credit_limit: None,
+            remaining_credit: None,
+            locked: false,
+            precision: Precision::new(8, RoundingStrategy::ToZero),
+        }
+    }
+
+    #[test]
+    fn test_decimals_keep_their_scale() {
+        let batch = accounts_batch(&[account(1, dec!(1.5)), account(2, dec!(-0.0001)), account(3, dec!(12345678901234.5678))], 4).unwrap();
+
+        let available = batch.column(2).as_any().downcast_ref::<Decimal128Array>().unwrap();
+        assert_eq!(available.scale(), 4);
+        assert_eq!(available.values().to_vec(), [15000, -1, 123456789012345678]);
+        assert_eq!(available.value_as_string(0), "1.5000");
+        assert!(batch.column(5).is_null(0));
+        assert!(batch.column(1).as_any().downcast_ref::<StringArray>().unwrap().is_null(0));
+    }
+
+    #[test]
+    fn test_more_fractional_digits_fail() {
+        let err = accounts_batch(&[account(1, dec!(0.00001))], 4).unwrap_err();
+        assert_eq!(err.to_string(), "0.00001 does not fit into 4 fractional digits");
+
+        let batch = accounts_batch(&[account(1, dec!(0.00001))], 8).unwrap();
+        assert_eq!(batch.column(2).as_any().downcast_ref::<Decimal128Array>().unwrap().value_as_string(0), "0.00001000");
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(ColumnarFormat::from_path("accounts.parquet"), ColumnarFormat::Parquet);
+        assert_eq!(ColumnarFormat::from_path("accounts.arrow"), ColumnarFormat::ArrowIpc);
+        assert_eq!(ColumnarFormat::from_path("history.ipc"), ColumnarFormat::ArrowIpc);
+    }
+}
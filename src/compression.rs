@@ -0,0 +1,197 @@
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Compression of a file by its extension, `.gz` or `.zst`
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Compression of a stream by the magic bytes it starts with
+    pub fn detect(head: &[u8]) -> Option<Self> {
+        if head.starts_with(&GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if head.starts_with(&ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!("Unknown compression {}, expected gzip or zstd", value)),
+        }
+    }
+}
+
+type Peeked<R> = io::Chain<Cursor<Vec<u8>>, R>;
+
+/// Decompressed input, the compression is detected from the magic bytes unless it is given.
+/// Inputs which are not compressed are read as they are, concatenated gzip members and zstd frames are read one
+/// after another.
+pub fn decompress<R: Read>(mut input: R, compression: Option<Compression>) -> io::Result<Decoder<R>> {
+    let mut head = Vec::with_capacity(ZSTD_MAGIC.len());
+    (&mut input).take(ZSTD_MAGIC.len() as u64).read_to_end(&mut head)?;
+
+    let compression = compression.or_else(|| Compression::detect(&head));
+    let input = Cursor::new(head).chain(input);
+
+    Ok(match compression {
+        Some(Compression::Gzip) => Decoder::Gzip(MultiGzDecoder::new(input)),
+        Some(Compression::Zstd) => Decoder::Zstd(zstd::Decoder::new(input)?),
+        None => Decoder::Plain(input),
+    })
+}
+
+/// Input decompressed on the fly, `Send` whenever the underlying reader is
+pub enum Decoder<R: Read> {
+    Plain(Peeked<R>),
+    Gzip(MultiGzDecoder<Peeked<R>>),
+    Zstd(zstd::Decoder<'static, BufReader<Peeked<R>>>),
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Decoder::Plain(input) => input.read(buf),
+            Decoder::Gzip(decoder) => decoder.read(buf),
+            Decoder::Zstd(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+/// Output compressed on the fly, `finish` has to be called after the last write to complete the stream
+pub enum Encoder<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(output: W, compression: Option<Compression>) -> io::Result<Self> {
+        Ok(match compression {
+            Some(Compression::Gzip) => Encoder::Gzip(GzEncoder::new(output, flate2::Compression::default())),
+            Some(Compression::Zstd) => Encoder::Zstd(zstd::Encoder::new(output, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+            None => Encoder::Plain(output),
+        })
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        let mut output = match self {
+            Encoder::Plain(output) => output,
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+
+        output.flush()?;
+        Ok(output)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(output) => output.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(output) => output.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Creates the file compressed according to its extension
+pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Encoder<File>> {
+    let compression = Compression::from_path(&path);
+    Encoder::new(File::create(path)?, compression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &[u8] = b"type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+    fn compress(data: &[u8], compression: Option<Compression>) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new(), compression).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn read(input: &[u8], compression: Option<Compression>) -> Vec<u8> {
+        let mut output = Vec::new();
+        decompress(input, compression).unwrap().read_to_end(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_round_trip_detected_by_magic_bytes() {
+        for compression in [None, Some(Compression::Gzip), Some(Compression::Zstd)] {
+            let compressed = compress(TEXT, compression);
+            assert_eq!(Compression::detect(&compressed), compression);
+            assert_eq!(read(&compressed, None), TEXT);
+        }
+    }
+
+    #[test]
+    fn test_concatenated_streams() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let mut compressed = compress(b"type,client,tx,amount\n", Some(compression));
+            compressed.extend(compress(b"deposit,1,1,1.0\n", Some(compression)));
+            assert_eq!(read(&compressed, None), TEXT);
+        }
+    }
+
+    #[test]
+    fn test_short_and_plain_inputs() {
+        assert_eq!(read(b"", None), b"");
+        assert_eq!(read(b"ab", None), b"ab");
+        assert_eq!(read(TEXT, None), TEXT);
+    }
+
+    #[test]
+    fn test_given_compression_wins() {
+        let mut output = Vec::new();
+        assert!(decompress(TEXT, Some(Compression::Gzip)).unwrap().read_to_end(&mut output).is_err());
+    }
+
+    #[test]
+    fn test_compression_from_path_and_name() {
+        assert_eq!(Compression::from_path("transactions.csv.gz"), Some(Compression::Gzip));
+        assert_eq!(Compression::from_path("transactions.jsonl.zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::from_path("transactions.csv"), None);
+        assert_eq!("zstd".parse(), Ok(Compression::Zstd));
+        assert!("lz4".parse::<Compression>().is_err());
+    }
+}
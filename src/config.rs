@@ -0,0 +1,113 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::compression::Compression;
+use crate::credit::CreditLimits;
+use crate::fees::FeeSchedule;
+use crate::limits::LimitsConfig;
+use crate::precision::PrecisionConfig;
+
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    /// Keep a per-account log of every applied operation, needed for statements
+    pub history: bool,
+    /// Post balanced ledger entries for every operation and verify them at shutdown
+    pub double_entry: bool,
+    /// Validate the account balances after every transaction and panic on a violation
+    pub check_invariants: bool,
+    /// Number of fractional digits and rounding per currency, used for input amounts and output balances
+    pub precision: PrecisionConfig,
+    /// Fees charged on withdrawals and chargebacks, the output gets a `fees` column when set
+    pub fees: Option<FeeSchedule>,
+    /// Initial credit limits, also enables the `credit_limit` admin transactions and the credit output columns
+    pub credit_limits: Option<CreditLimits>,
+    /// Deposit, withdrawal and velocity limits, globally and per client
+    pub limits: LimitsConfig,
+    /// Disputes arriving later than this after the disputed transaction are rejected, needs timestamps
+    pub dispute_window: Option<Duration>,
+    /// What happens to an account whose worker panicked or died
+    pub supervision: SupervisionPolicy,
+    /// How the accounts are distributed over the workers
+    pub mode: EngineMode,
+    /// Capacity of every worker channel, `WORKER_CHANNEL_SIZE` when not set and at least 1
+    pub channel_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EngineMode {
+    /// Every client gets its own worker task
+    #[default]
+    ActorPerClient,
+    /// A fixed number of workers, the client `c` is served by the worker `c % n`
+    Sharded(usize),
+    /// No workers, transactions are applied by the caller of the engine before the call returns
+    Synchronous,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SupervisionPolicy {
+    /// The account keeps its balances and rejects every later transaction
+    #[default]
+    Fault,
+    /// The account is rebuilt by replaying its applied transactions, keeps a journal of them in memory
+    Rebuild,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum InputFormat {
+    /// Rows with a header, see the README for the columns
+    #[default]
+    Csv,
+    /// One JSON transaction per line with the same fields as the CSV columns, amounts as strings
+    Jsonl,
+}
+
+impl InputFormat {
+    /// Format of a file by its extension, CSV unless it ends with `.jsonl`, also behind `.gz` or `.zst`
+    pub fn from_path(path: &str) -> Self {
+        let path = path.strip_suffix(".gz").or_else(|| path.strip_suffix(".zst")).unwrap_or(path);
+        if path.ends_with(".jsonl") {
+            InputFormat::Jsonl
+        } else {
+            InputFormat::Csv
+        }
+    }
+}
+
+/// Parsing of CSV inputs in chunks split at record boundaries, the chunks are parsed concurrently and
+/// their rows are handed to the engine in the input order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParallelParsing {
+    /// Number of chunks parsed at the same time
+    pub threads: usize,
+    /// Bytes per chunk, a chunk grows when a single record is longer
+    pub chunk_size: usize,
+}
+
+impl Default for ParallelParsing {
+    fn default() -> Self {
+        ParallelParsing {
+            threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+            chunk_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AppConfig {
+    pub engine: EngineConfig,
+    pub ordered_output: bool,
+    pub input_format: InputFormat,
+    /// CSV inputs are parsed on one thread when not set
+    pub parallel_parsing: Option<ParallelParsing>,
+    /// Compression of the input, detected from its first bytes when not set
+    pub input_compression: Option<Compression>,
+    /// Compression of the account output, written plain when not set
+    pub output_compression: Option<Compression>,
+    /// File the balances of all clients are written to with the currency column when the run stops,
+    /// compressed when it ends with `.gz` or `.zst`
+    pub snapshot: Option<PathBuf>,
+    /// Parquet or Arrow IPC files of the accounts and the history written when the run stops
+    #[cfg(feature = "columnar")]
+    pub columnar: Option<crate::columnar::ColumnarOutput>,
+}
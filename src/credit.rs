@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Read;
+
+use csv::ReaderBuilder;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::currency::{Currency, DEFAULT_CURRENCY};
+use crate::decimal::deserialize_option_decimal_exact;
+
+/// Approved credit lines per client and currency
+pub type CreditLimits = HashMap<u16, HashMap<Currency, Decimal>>;
+
+#[derive(Debug, Deserialize)]
+struct CreditLimitEntity {
+    client: u16,
+    #[serde(deserialize_with = "deserialize_option_decimal_exact")]
+    limit: Option<Decimal>,
+    #[serde(default)]
+    currency: Option<Currency>,
+}
+
+/// Reads limits from a CSV with the `client,limit[,currency]` columns
+pub fn load_credit_limits<R: Read>(input: R) -> Result<CreditLimits, Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input);
+
+    let mut limits = CreditLimits::new();
+
+    for result in reader.deserialize::<CreditLimitEntity>() {
+        let entity = result?;
+        let limit = entity.limit.unwrap_or_default();
+
+        if limit.is_sign_negative() {
+            return Err(format!("Credit limit of client {} is negative", entity.client).into());
+        }
+
+        let currency = entity.currency.unwrap_or_else(|| DEFAULT_CURRENCY.to_string());
+        limits.entry(entity.client).or_default().insert(currency, limit);
+    }
+
+    Ok(limits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_load_credit_limits() {
+        let input = "client,limit,currency\n1,100.0\n1,50,EUR\n2, 20 ,";
+        let limits = load_credit_limits(input.as_bytes()).unwrap();
+
+        assert_eq!(limits[&1]["USD"], dec!(100.0));
+        assert_eq!(limits[&1]["EUR"], dec!(50));
+        assert_eq!(limits[&2]["USD"], dec!(20));
+    }
+
+    #[test]
+    fn test_load_negative_credit_limit() {
+        assert!(load_credit_limits("client,limit\n1,-1".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,5 @@
+/// ISO 4217 style currency code as it comes in the input, e.g. `USD`
+pub type Currency = String;
+
+/// Currency of the transactions without the currency column
+pub const DEFAULT_CURRENCY: &str = "USD";
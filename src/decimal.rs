@@ -1,31 +1,64 @@
-use rust_decimal::{ Decimal, RoundingStrategy };
-use serde::{Serializer, Deserialize, Deserializer};
+use rust_decimal::Decimal;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
 use serde::de::Error as SerdeError;
 use std::str::FromStr;
 
+use crate::precision::Precision;
+
+// Default precision, the configured one is applied by `PrecisionConfig` on input and by `Rounded` on output
 pub const DECIMAL_PRECISION: u32 = 4;
 
+/// Amount written with the precision of its currency, output amounts are rounded only here
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rounded(pub Decimal, pub Precision);
+
+impl Serialize for Rounded {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.1.round(self.0).to_string().as_str())
+    }
+}
+
+/// Writes the amount with the default precision
 pub fn serialize_decimal<S>(decimal: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {    
-    let rounded = decimal.round_dp_with_strategy(DECIMAL_PRECISION, RoundingStrategy::ToZero);
-    serializer.serialize_str(rounded.to_string().as_str())
+    Rounded(*decimal, Precision::default()).serialize(serializer)
 }
 
+pub fn serialize_option_decimal<S>(decimal: &Option<Decimal>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match decimal {
+        Some(decimal) => serialize_decimal(decimal, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Reads the amount with the default precision
 pub fn deserialize_option_decimal<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
 where
     D: Deserializer<'de>,
 {    
-    let decimal_str = String::deserialize(deserializer)?;
-    if decimal_str.is_empty() {
-        return Ok(None);
-    }
+    Ok(deserialize_option_decimal_exact(deserializer)?.map(|decimal| Precision::default().round(decimal)))
+}
+
+/// Reads the amount with all its digits, for amounts the precision of their currency is applied to later
+pub fn deserialize_option_decimal_exact<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // Empty CSV fields and JSON nulls are both missing amounts
+    let decimal_str = match Option::<String>::deserialize(deserializer)? {
+        Some(decimal_str) if !decimal_str.is_empty() => decimal_str,
+        _ => return Ok(None),
+    };
     
     let result = Decimal::from_str(&decimal_str)
         .map_err(D::Error::custom)?;
 
-    Ok(Some(result.round_dp_with_strategy(DECIMAL_PRECISION, RoundingStrategy::ToZero)))
+    Ok(Some(result))
 }
 
 #[cfg(test)]
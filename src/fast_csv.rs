@@ -0,0 +1,204 @@
+use std::num::ParseIntError;
+use std::str::{self, FromStr};
+
+use csv::{ByteRecord, Position, StringRecord};
+use rust_decimal::Decimal;
+use serde::de::{value, Deserialize, IntoDeserializer};
+
+use crate::transaction::{TransactionEntity, TransactionType};
+
+/// Parses transactions straight from the bytes of a `ByteRecord`, without the allocations of serde for every field.
+/// Records the fast path is not sure about go through serde, so both accept the same inputs with the same errors.
+/// The fields are trimmed by the parser, the records can be read with `csv::Trim::Headers`.
+#[derive(Debug, Clone)]
+pub struct RecordParser {
+    headers: StringRecord,
+    // Not set when the header has duplicate names or lacks a required column, every record goes through serde then
+    columns: Option<Columns>,
+}
+
+#[derive(Debug, Clone)]
+struct Columns {
+    transaction_type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    currency: Option<usize>,
+    to_client: Option<usize>,
+    timestamp: Option<usize>,
+    // Ignored like by serde, but a record with invalid UTF-8 in them is still rejected
+    unknown: Vec<usize>,
+    len: usize,
+}
+
+impl RecordParser {
+    pub fn new(headers: &StringRecord) -> Self {
+        RecordParser {
+            headers: headers.clone(),
+            columns: Columns::new(headers),
+        }
+    }
+
+    /// Transaction of the record or the error serde gives for it. Fails only for records with invalid UTF-8,
+    /// the same records `csv::Reader::read_record` fails for.
+    pub fn parse(&self, record: &ByteRecord) -> Result<Result<TransactionEntity, String>, String> {
+        if let Some(transaction) = self.columns.as_ref().and_then(|columns| columns.parse(record)) {
+            return Ok(Ok(transaction));
+        }
+
+        // Trimmed like by the csv reader before the validation, so the errors name the same byte
+        let mut record = record.clone();
+        record.trim();
+
+        let position = record.position().cloned().unwrap_or_else(Position::new);
+        let mut record = match StringRecord::from_byte_record(record) {
+            Ok(record) => record,
+            Err(err) => {
+                let err = err.utf8_error();
+                return Err(format!(
+                    "CSV parse error: record {} (line {}, field: {}, byte: {}): {}",
+                    position.record(), position.line(), err.field(), position.byte(), err
+                ));
+            }
+        };
+
+        // A `StringRecord` is trimmed of all Unicode whitespace, a `ByteRecord` only of the ASCII one
+        record.trim();
+        Ok(record.deserialize(Some(&self.headers)).map_err(|e| e.to_string()))
+    }
+}
+
+impl Columns {
+    fn new(headers: &StringRecord) -> Option<Self> {
+        let position = |name: &str| -> Option<Option<usize>> {
+            let mut found = headers.iter().enumerate().filter(|(_, header)| *header == name).map(|(index, _)| index);
+            match (found.next(), found.next()) {
+                (_, Some(_)) => None,
+                (index, None) => Some(index),
+            }
+        };
+
+        let known = ["type", "client", "tx", "amount", "currency", "to_client", "timestamp"];
+        Some(Columns {
+            transaction_type: position("type")??,
+            client: position("client")??,
+            tx: position("tx")??,
+            amount: position("amount")?,
+            currency: position("currency")?,
+            to_client: position("to_client")?,
+            timestamp: position("timestamp")?,
+            unknown: (0..headers.len()).filter(|index| !known.contains(&&headers[*index])).collect(),
+            len: headers.len(),
+        })
+    }
+
+    fn parse(&self, record: &ByteRecord) -> Option<TransactionEntity> {
+        if record.len() != self.len || self.unknown.iter().any(|index| str::from_utf8(&record[*index]).is_err()) {
+            return None;
+        }
+
+        let field = |index: usize| str::from_utf8(&record[index]).ok().map(str::trim);
+        // Outer `None` sends the record to serde, inner `None` is a missing or empty value
+        let optional = |index: Option<usize>| match index {
+            Some(index) => field(index).map(|value| Some(value).filter(|value| !value.is_empty())),
+            None => Some(None),
+        };
+
+        let transaction_type: value::StrDeserializer<value::Error> = field(self.transaction_type)?.into_deserializer();
+        let transaction_type = TransactionType::deserialize(transaction_type).ok()?;
+
+        let amount = match optional(self.amount)? {
+            Some(amount) => Some(Decimal::from_str(amount).ok()?),
+            None => None,
+        };
+        let to_client = match optional(self.to_client)? {
+            Some(to_client) => Some(parse_int(to_client, u16::from_str_radix)?),
+            None => None,
+        };
+        let timestamp = match optional(self.timestamp)? {
+            Some(timestamp) => Some(parse_int(timestamp, u64::from_str_radix)?),
+            None => None,
+        };
+
+        Some(TransactionEntity {
+            transaction_type,
+            client: parse_int(field(self.client)?, u16::from_str_radix)?,
+            tx: parse_int(field(self.tx)?, u32::from_str_radix)?,
+            amount,
+            currency: optional(self.currency)?.map(str::to_string),
+            to_client,
+            timestamp,
+            seq: None,
+        })
+    }
+}
+
+// Like the csv deserializer, which also takes hexadecimal numbers with the `0x` prefix
+fn parse_int<T: FromStr>(value: &str, from_str_radix: fn(&str, u32) -> Result<T, ParseIntError>) -> Option<T> {
+    match value.strip_prefix("0x") {
+        Some(hex) => from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::ReaderBuilder;
+    use rust_decimal_macros::dec;
+
+    fn parse(input: &str) -> Vec<Result<Result<TransactionEntity, String>, String>> {
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+
+        let parser = RecordParser::new(reader.headers().unwrap());
+        let mut record = ByteRecord::new();
+        let mut rows = Vec::new();
+        while reader.read_byte_record(&mut record).unwrap() {
+            rows.push(parser.parse(&record));
+        }
+
+        rows
+    }
+
+    #[test]
+    fn test_fast_path() {
+        let header = "type,client,tx,amount,currency,to_client,timestamp,note\n";
+        let rows = parse(&format!("{}transfer, 0x1f, +7, 1.50, EUR, 2, 1700000000, x\n", header));
+
+        let transaction = rows[0].clone().unwrap().unwrap();
+        assert_eq!(transaction.transaction_type, TransactionType::Transfer);
+        assert_eq!((transaction.client, transaction.tx), (31, 7));
+        assert_eq!(transaction.amount, Some(dec!(1.50)));
+        assert_eq!(transaction.currency.as_deref(), Some("EUR"));
+        assert_eq!((transaction.to_client, transaction.timestamp), (Some(2), Some(1_700_000_000)));
+    }
+
+    #[test]
+    fn test_fallback_gives_serde_errors() {
+        let rows = parse("type,client,tx,amount\nDeposit,1,1,1.0\ndeposit,1,1\ndeposit,70000,1,1.0\n");
+
+        assert!(rows[0].as_ref().unwrap().as_ref().unwrap_err().contains("unknown variant `Deposit`"));
+        assert_eq!(rows[1].as_ref().unwrap().as_ref().unwrap().amount, None);
+        assert!(rows[2].as_ref().unwrap().as_ref().unwrap_err().contains("number too large"));
+    }
+
+    #[test]
+    fn test_duplicate_columns_go_through_serde() {
+        let rows = parse("type,client,tx,amount,amount\ndeposit,1,1,1.0,2.0\n");
+        assert!(rows[0].as_ref().unwrap().as_ref().unwrap_err().contains("duplicate field"));
+    }
+
+    #[test]
+    fn test_invalid_utf8_fails_the_reading() {
+        let mut reader = ReaderBuilder::new().from_reader(&b"type,client,tx,amount\ndeposit,1,1,\xff\n"[..]);
+        let parser = RecordParser::new(reader.headers().unwrap());
+        let mut record = ByteRecord::new();
+        reader.read_byte_record(&mut record).unwrap();
+
+        let err = parser.parse(&record).unwrap_err();
+        assert!(err.starts_with("CSV parse error: record 1 (line 2, field: 3, byte: 22): invalid utf-8"), "{}", err);
+    }
+}
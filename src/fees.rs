@@ -0,0 +1,47 @@
+use rust_decimal::Decimal;
+
+/// Fees charged from the client available funds and posted to the fee income house account
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeeSchedule {
+    pub withdrawal_fixed: Decimal,
+    /// Percent of the withdrawn amount, `1.5` means 1.5%
+    pub withdrawal_percent: Decimal,
+    pub chargeback_penalty: Decimal,
+}
+
+impl FeeSchedule {
+    pub fn withdrawal_fee(&self, amount: Decimal) -> Decimal {
+        self.withdrawal_fixed + amount * self.withdrawal_percent / Decimal::ONE_HUNDRED
+    }
+
+    pub fn chargeback_fee(&self) -> Decimal {
+        self.chargeback_penalty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_withdrawal_fee() {
+        let fees = FeeSchedule {
+            withdrawal_fixed: dec!(0.5),
+            withdrawal_percent: dec!(1.5),
+            chargeback_penalty: dec!(15),
+        };
+
+        assert_eq!(fees.withdrawal_fee(dec!(100)), dec!(2.0));
+        assert_eq!(fees.withdrawal_fee(dec!(0.01)), dec!(0.50015));
+        assert_eq!(fees.chargeback_fee(), dec!(15));
+    }
+
+    #[test]
+    fn test_default_schedule_is_free() {
+        let fees = FeeSchedule::default();
+
+        assert!(fees.withdrawal_fee(dec!(100)).is_zero());
+        assert!(fees.chargeback_fee().is_zero());
+    }
+}
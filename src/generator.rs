@@ -0,0 +1,532 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::str::FromStr;
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+use crate::config::InputFormat;
+use crate::decimal::DECIMAL_PRECISION;
+
+/// Relative frequency of every row type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TypeWeights {
+    pub deposit: u32,
+    pub withdrawal: u32,
+    pub dispute: u32,
+    pub resolve: u32,
+    pub chargeback: u32,
+    pub transfer: u32,
+}
+
+// The mix of the former generate_txs.py script
+impl Default for TypeWeights {
+    fn default() -> Self {
+        TypeWeights { deposit: 45, withdrawal: 40, dispute: 8, resolve: 4, chargeback: 3, transfer: 0 }
+    }
+}
+
+/// Six comma-separated numbers in the order deposit, withdrawal, dispute, resolve, chargeback, transfer
+impl FromStr for TypeWeights {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let weights = s.split(',').map(|weight| weight.trim().parse()).collect::<Result<Vec<u32>, _>>()?;
+
+        match weights[..] {
+            [deposit, withdrawal, dispute, resolve, chargeback, transfer] if weights.iter().any(|weight| *weight > 0) => {
+                Ok(TypeWeights { deposit, withdrawal, dispute, resolve, chargeback, transfer })
+            }
+            _ => Err("Weights need six numbers, not all of them zero".into()),
+        }
+    }
+}
+
+impl TypeWeights {
+    fn total(&self) -> u64 {
+        [self.deposit, self.withdrawal, self.dispute, self.resolve, self.chargeback, self.transfer]
+            .iter()
+            .map(|weight| *weight as u64)
+            .sum()
+    }
+}
+
+/// Amounts are always positive and have at most `DECIMAL_PRECISION` fractional digits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AmountDistribution {
+    Uniform { min: Decimal, max: Decimal },
+    /// Mostly small amounts with a long tail, like real card payments
+    Exponential { mean: Decimal },
+}
+
+/// `uniform:<min>:<max>` or `exponential:<mean>`
+impl FromStr for AmountDistribution {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+
+        let distribution = match parts[..] {
+            ["uniform", min, max] => AmountDistribution::Uniform { min: min.parse()?, max: max.parse()? },
+            ["exponential", mean] => AmountDistribution::Exponential { mean: mean.parse()? },
+            _ => return Err(format!("Unknown amount distribution {}", s).into()),
+        };
+
+        match distribution {
+            // The amounts are drawn in smallest units, which have to fit in an i64
+            AmountDistribution::Uniform { min, max } if min <= Decimal::ZERO || min > max || Generator::units(max) == i64::MAX => {
+                Err(format!("Amount range {} is invalid", s).into())
+            }
+            AmountDistribution::Exponential { mean } if mean <= Decimal::ZERO => Err(format!("Amount mean {} is invalid", s).into()),
+            distribution => Ok(distribution),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    pub rows: usize,
+    /// Clients are numbered from 1
+    pub clients: u16,
+    pub seed: u64,
+    pub weights: TypeWeights,
+    pub deposit_amounts: AmountDistribution,
+    pub withdrawal_amounts: AmountDistribution,
+    /// Share of disputes which hold only a part of the deposit
+    pub partial_dispute_ratio: f64,
+    /// Share of rows replaced with rows the reader rejects
+    pub invalid_ratio: f64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            rows: 1_000_000,
+            clients: u16::MAX - 1,
+            seed: 0,
+            weights: TypeWeights::default(),
+            deposit_amounts: AmountDistribution::Uniform { min: Decimal::new(1, 2), max: Decimal::new(10_000, 0) },
+            withdrawal_amounts: AmountDistribution::Uniform { min: Decimal::new(1, 2), max: Decimal::new(1_000, 0) },
+            partial_dispute_ratio: 0.0,
+            invalid_ratio: 0.0,
+        }
+    }
+}
+
+// SplitMix64, small and stable, so a seed produces the same rows with every build
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// Uniform in [0, 1)
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        self.unit() < probability
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> Option<T> {
+        if items.is_empty() {
+            return None;
+        }
+
+        Some(items[self.below(items.len() as u64) as usize])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RowType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Transfer,
+}
+
+impl RowType {
+    fn name(&self) -> &'static str {
+        match self {
+            RowType::Deposit => "deposit",
+            RowType::Withdrawal => "withdrawal",
+            RowType::Dispute => "dispute",
+            RowType::Resolve => "resolve",
+            RowType::Chargeback => "chargeback",
+            RowType::Transfer => "transfer",
+        }
+    }
+}
+
+enum Row {
+    Transaction {
+        row_type: RowType,
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+        to_client: Option<u16>,
+    },
+    Invalid(&'static str, &'static str),
+}
+
+// Rows the reader can't turn into a transaction, as CSV and as JSONL
+const INVALID_ROWS: [(&str, &str); 4] = [
+    ("refund,1,1,1.0", r#"{"type":"refund","client":1,"tx":1,"amount":"1.0"}"#),
+    ("deposit,1,1,abc", r#"{"type":"deposit","client":1,"tx":1,"amount":"abc"}"#),
+    ("deposit,-1,1,1.0", r#"{"type":"deposit","client":-1,"tx":1,"amount":"1.0"}"#),
+    ("deposit,1", r#"{"type":"deposit","#),
+];
+
+/// Produces the rows of a configuration, the same seed always gives the same rows
+pub struct Generator {
+    config: GeneratorConfig,
+    rng: Rng,
+    next_tx: u32,
+    // Deposits which can be disputed and the disputes which can be resolved or charged back, per client
+    deposits: HashMap<u16, Vec<(u32, Decimal)>>,
+    disputes: HashMap<u16, Vec<u32>>,
+}
+
+impl Generator {
+    pub fn new(config: GeneratorConfig) -> Self {
+        let rng = Rng(config.seed);
+
+        Generator {
+            config,
+            rng,
+            next_tx: 1,
+            deposits: HashMap::new(),
+            disputes: HashMap::new(),
+        }
+    }
+
+    /// Writes all the rows, CSV with a header or one JSON object per line
+    pub fn write<W: Write>(&mut self, format: InputFormat, mut output: W) -> Result<(), Box<dyn Error>> {
+        let weights = self.config.weights;
+        // Disputes, resolves and chargebacks need something to reference, without other rows nothing is generated
+        if weights.deposit == 0 && weights.withdrawal == 0 && weights.transfer == 0 && self.config.invalid_ratio <= 0.0 {
+            return Err("Weights need deposits, withdrawals or transfers".into());
+        }
+
+        let with_transfers = self.config.weights.transfer > 0;
+
+        if format == InputFormat::Csv {
+            let header = if with_transfers { "type,client,tx,amount,to_client" } else { "type,client,tx,amount" };
+            writeln!(output, "{}", header)?;
+        }
+
+        let mut written = 0;
+        while written < self.config.rows {
+            let row = match self.next_row() {
+                Some(row) => row,
+                None => continue,
+            };
+
+            match format {
+                InputFormat::Csv => Self::write_csv(&row, with_transfers, &mut output)?,
+                InputFormat::Jsonl => Self::write_jsonl(&row, &mut output)?,
+            }
+            written += 1;
+        }
+
+        output.flush()?;
+        Ok(())
+    }
+
+    fn write_csv<W: Write>(row: &Row, with_transfers: bool, output: &mut W) -> Result<(), Box<dyn Error>> {
+        match row {
+            Row::Transaction { row_type, client, tx, amount, to_client } => {
+                write!(output, "{},{},{},", row_type.name(), client, tx)?;
+                if let Some(amount) = amount {
+                    write!(output, "{}", amount)?;
+                }
+                if with_transfers {
+                    write!(output, ",")?;
+                    if let Some(to_client) = to_client {
+                        write!(output, "{}", to_client)?;
+                    }
+                }
+                writeln!(output)?;
+            }
+            Row::Invalid(csv, _) => writeln!(output, "{}", csv)?,
+        }
+
+        Ok(())
+    }
+
+    fn write_jsonl<W: Write>(row: &Row, output: &mut W) -> Result<(), Box<dyn Error>> {
+        match row {
+            Row::Transaction { row_type, client, tx, amount, to_client } => {
+                write!(output, r#"{{"type":"{}","client":{},"tx":{}"#, row_type.name(), client, tx)?;
+                if let Some(amount) = amount {
+                    write!(output, r#","amount":"{}""#, amount)?;
+                }
+                if let Some(to_client) = to_client {
+                    write!(output, r#","to_client":{}"#, to_client)?;
+                }
+                writeln!(output, "}}")?;
+            }
+            Row::Invalid(_, jsonl) => writeln!(output, "{}", jsonl)?,
+        }
+
+        Ok(())
+    }
+
+    // `None` when the picked type has nothing to reference yet, the caller just tries again
+    fn next_row(&mut self) -> Option<Row> {
+        if self.config.invalid_ratio > 0.0 && self.rng.chance(self.config.invalid_ratio) {
+            let (csv, jsonl) = INVALID_ROWS[self.rng.below(INVALID_ROWS.len() as u64) as usize];
+            return Some(Row::Invalid(csv, jsonl));
+        }
+
+        let client = self.rng.below(self.config.clients.max(1) as u64) as u16 + 1;
+        let row_type = self.row_type();
+
+        let row = match row_type {
+            RowType::Deposit => {
+                let tx = self.take_tx();
+                let amount = self.amount(self.config.deposit_amounts);
+                self.deposits.entry(client).or_default().push((tx, amount));
+                Row::Transaction { row_type, client, tx, amount: Some(amount), to_client: None }
+            }
+            RowType::Withdrawal => {
+                let amount = self.amount(self.config.withdrawal_amounts);
+                Row::Transaction { row_type, client, tx: self.take_tx(), amount: Some(amount), to_client: None }
+            }
+            RowType::Transfer => {
+                let amount = self.amount(self.config.withdrawal_amounts);
+                let to_client = self.rng.below(self.config.clients.max(1) as u64) as u16 + 1;
+                Row::Transaction { row_type, client, tx: self.take_tx(), amount: Some(amount), to_client: Some(to_client) }
+            }
+            RowType::Dispute => {
+                let (tx, deposited) = self.rng.pick(self.deposits.get(&client).map(Vec::as_slice).unwrap_or_default())?;
+                let amount = if self.rng.chance(self.config.partial_dispute_ratio) {
+                    Some(self.fraction_of(deposited))
+                } else {
+                    None
+                };
+
+                self.disputes.entry(client).or_default().push(tx);
+                Row::Transaction { row_type, client, tx, amount, to_client: None }
+            }
+            RowType::Resolve | RowType::Chargeback => {
+                let disputes = self.disputes.get_mut(&client)?;
+                if disputes.is_empty() {
+                    return None;
+                }
+
+                let tx = disputes.swap_remove(self.rng.below(disputes.len() as u64) as usize);
+                Row::Transaction { row_type, client, tx, amount: None, to_client: None }
+            }
+        };
+
+        Some(row)
+    }
+
+    fn row_type(&mut self) -> RowType {
+        let weights = self.config.weights;
+        let mut roll = self.rng.below(weights.total().max(1));
+
+        for (weight, row_type) in [
+            (weights.deposit, RowType::Deposit),
+            (weights.withdrawal, RowType::Withdrawal),
+            (weights.dispute, RowType::Dispute),
+            (weights.resolve, RowType::Resolve),
+            (weights.chargeback, RowType::Chargeback),
+            (weights.transfer, RowType::Transfer),
+        ] {
+            if roll < weight as u64 {
+                return row_type;
+            }
+            roll -= weight as u64;
+        }
+
+        RowType::Deposit
+    }
+
+    fn take_tx(&mut self) -> u32 {
+        let tx = self.next_tx;
+        self.next_tx = self.next_tx.wrapping_add(1);
+        tx
+    }
+
+    fn amount(&mut self, distribution: AmountDistribution) -> Decimal {
+        let smallest = Decimal::new(1, DECIMAL_PRECISION);
+
+        let amount = match distribution {
+            AmountDistribution::Uniform { min, max } => {
+                let min_units = Self::units(min);
+                // The units saturate for huge amounts, the range is then capped at the largest one
+                let span = Self::units(max).checked_sub(min_units).and_then(|span| span.checked_add(1)).unwrap_or(i64::MAX).max(1);
+                Decimal::new(min_units.saturating_add(self.rng.below(span as u64) as i64), DECIMAL_PRECISION)
+            }
+            AmountDistribution::Exponential { mean } => {
+                let mean_units = Self::units(mean) as f64;
+                let units = -mean_units * (1.0 - self.rng.unit()).ln();
+                Decimal::new(units.round() as i64, DECIMAL_PRECISION)
+            }
+        };
+
+        amount.max(smallest).normalize()
+    }
+
+    fn fraction_of(&mut self, amount: Decimal) -> Decimal {
+        let units = Self::units(amount).max(1);
+        Decimal::new(self.rng.below(units as u64) as i64 + 1, DECIMAL_PRECISION).normalize()
+    }
+
+    // Amount in the smallest units of the default precision
+    fn units(amount: Decimal) -> i64 {
+        (amount * Decimal::new(10i64.pow(DECIMAL_PRECISION), 0)).trunc().to_i64().unwrap_or(i64::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn generate(config: GeneratorConfig, format: InputFormat) -> String {
+        let mut output = Vec::new();
+        Generator::new(config).write(format, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn config(rows: usize, seed: u64) -> GeneratorConfig {
+        GeneratorConfig { rows, clients: 10, seed, ..Default::default() }
+    }
+
+    #[test]
+    fn test_same_seed_same_rows() {
+        assert_eq!(generate(config(1000, 7), InputFormat::Csv), generate(config(1000, 7), InputFormat::Csv));
+        assert_ne!(generate(config(1000, 7), InputFormat::Csv), generate(config(1000, 8), InputFormat::Csv));
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let output = generate(config(500, 1), InputFormat::Csv);
+        let mut lines = output.lines();
+
+        assert_eq!(lines.next(), Some("type,client,tx,amount"));
+        assert_eq!(lines.clone().count(), 500);
+        for line in lines {
+            let fields: Vec<&str> = line.split(',').collect();
+            assert_eq!(fields.len(), 4, "{}", line);
+            let client: u16 = fields[1].parse().unwrap();
+            assert!((1..=10).contains(&client));
+            if fields[0] == "deposit" || fields[0] == "withdrawal" {
+                let amount: Decimal = fields[3].parse().unwrap();
+                assert!(amount > Decimal::ZERO && amount.scale() <= DECIMAL_PRECISION, "{}", line);
+            } else {
+                assert_eq!(fields[3], "");
+            }
+        }
+    }
+
+    #[test]
+    fn test_disputes_reference_deposits_of_the_client() {
+        let config = GeneratorConfig {
+            weights: TypeWeights { deposit: 1, withdrawal: 0, dispute: 1, resolve: 1, chargeback: 1, transfer: 0 },
+            ..config(2000, 3)
+        };
+        let output = generate(config, InputFormat::Csv);
+
+        let mut deposits = HashMap::new();
+        for line in output.lines().skip(1) {
+            let fields: Vec<&str> = line.split(',').collect();
+            match fields[0] {
+                "deposit" => {
+                    deposits.insert(fields[2], fields[1]);
+                }
+                _ => assert_eq!(deposits.get(fields[2]), Some(&fields[1]), "{}", line),
+            }
+        }
+    }
+
+    #[test]
+    fn test_jsonl_rows_with_transfers_and_partial_disputes() {
+        let config = GeneratorConfig {
+            weights: TypeWeights { deposit: 5, withdrawal: 0, dispute: 5, resolve: 0, chargeback: 0, transfer: 2 },
+            partial_dispute_ratio: 1.0,
+            ..config(300, 4)
+        };
+        let output = generate(config, InputFormat::Jsonl);
+
+        let rows: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(rows.len(), 300);
+        assert!(rows.iter().any(|row| row["type"] == "transfer" && row["to_client"].is_u64()));
+        assert!(rows.iter().filter(|row| row["type"] == "dispute").all(|row| row["amount"].is_string()));
+    }
+
+    #[test]
+    fn test_invalid_rows() {
+        let config = GeneratorConfig { invalid_ratio: 1.0, ..config(20, 5) };
+
+        for line in generate(config.clone(), InputFormat::Csv).lines().skip(1) {
+            assert!(INVALID_ROWS.iter().any(|(csv, _)| *csv == line));
+        }
+        for line in generate(config, InputFormat::Jsonl).lines() {
+            assert!(serde_json::from_str::<crate::transaction::TransactionEntity>(line).is_err());
+        }
+    }
+
+    #[test]
+    fn test_amount_distributions() {
+        let mut generator = Generator::new(config(0, 9));
+
+        for _ in 0..1000 {
+            let amount = generator.amount(AmountDistribution::Uniform { min: dec!(1.5), max: dec!(2) });
+            assert!(amount >= dec!(1.5) && amount <= dec!(2));
+
+            let amount = generator.amount(AmountDistribution::Exponential { mean: dec!(10) });
+            assert!(amount >= dec!(0.0001));
+        }
+    }
+
+    #[test]
+    fn test_amount_ranges_beyond_the_units() {
+        let mut generator = Generator::new(config(0, 9));
+
+        for min in [dec!(0.00001), dec!(1)] {
+            assert!(format!("uniform:{}:100000000000000000000", min).parse::<AmountDistribution>().is_err());
+
+            // Built directly the range isn't checked, the saturated units must not overflow
+            for _ in 0..100 {
+                let amount = generator.amount(AmountDistribution::Uniform { min, max: dec!(1e20) });
+                assert!(amount >= dec!(0.0001) && amount <= dec!(1e20), "{}", amount);
+            }
+        }
+        assert!("uniform:1:900000000000000".parse::<AmountDistribution>().is_ok());
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!("1,2,3,4,5,6".parse::<TypeWeights>().unwrap().transfer, 6);
+        assert!("1,2,3".parse::<TypeWeights>().is_err());
+        assert!("0,0,0,0,0,0".parse::<TypeWeights>().is_err());
+
+        let only_disputes = GeneratorConfig { weights: "0,0,1,1,1,0".parse().unwrap(), ..config(10, 1) };
+        assert!(Generator::new(only_disputes).write(InputFormat::Csv, Vec::new()).is_err());
+
+        assert_eq!(
+            "uniform:0.01:100".parse::<AmountDistribution>().unwrap(),
+            AmountDistribution::Uniform { min: dec!(0.01), max: dec!(100) }
+        );
+        assert_eq!("exponential:25".parse::<AmountDistribution>().unwrap(), AmountDistribution::Exponential { mean: dec!(25) });
+        assert!("uniform:5:1".parse::<AmountDistribution>().is_err());
+        assert!("normal:1".parse::<AmountDistribution>().is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use std::error::Error;
+use std::io::Write;
+
+use csv::WriterBuilder;
+use rust_decimal::Decimal;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::account::TransferLeg;
+use crate::currency::Currency;
+use crate::decimal::Rounded;
+use crate::precision::Precision;
+use crate::transaction::{TransactionStatus, TransactionType};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatementFormat {
+    Csv,
+    Jsonl,
+}
+
+/// Single applied operation together with the balances around it
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    // Sequence number the engine gave the transaction, empty for accounts used without the engine
+    pub seq: Option<u64>,
+    pub client: u16,
+    pub tx: u32,
+    pub transaction_type: TransactionType,
+    pub currency: Currency,
+    pub available_before: Decimal,
+    pub held_before: Decimal,
+    pub total_before: Decimal,
+    pub available_after: Decimal,
+    pub held_after: Decimal,
+    pub total_after: Decimal,
+    // Status of the referenced transaction, empty for operations which are not stored (withdrawals)
+    pub status_before: Option<TransactionStatus>,
+    pub status_after: Option<TransactionStatus>,
+    pub locked: bool,
+    /// Leg of a transfer, the two accounts of a transfer have entries with the same sequence number. Not written to
+    /// the statement.
+    pub leg: Option<TransferLeg>,
+    /// Precision of the currency, the amounts are rounded to it when written
+    pub precision: Precision,
+}
+
+impl Serialize for HistoryEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entry = serializer.serialize_struct("HistoryEntry", 14)?;
+        entry.serialize_field("seq", &self.seq)?;
+        entry.serialize_field("client", &self.client)?;
+        entry.serialize_field("tx", &self.tx)?;
+        entry.serialize_field("type", &self.transaction_type)?;
+        entry.serialize_field("currency", &self.currency)?;
+        entry.serialize_field("available_before", &Rounded(self.available_before, self.precision))?;
+        entry.serialize_field("held_before", &Rounded(self.held_before, self.precision))?;
+        entry.serialize_field("total_before", &Rounded(self.total_before, self.precision))?;
+        entry.serialize_field("available_after", &Rounded(self.available_after, self.precision))?;
+        entry.serialize_field("held_after", &Rounded(self.held_after, self.precision))?;
+        entry.serialize_field("total_after", &Rounded(self.total_after, self.precision))?;
+        entry.serialize_field("status_before", &self.status_before)?;
+        entry.serialize_field("status_after", &self.status_after)?;
+        entry.serialize_field("locked", &self.locked)?;
+        entry.end()
+    }
+}
+
+pub fn write_statement<W: Write>(entries: &[HistoryEntry], format: StatementFormat, mut output: W) -> Result<(), Box<dyn Error>> {
+    match format {
+        StatementFormat::Csv => {
+            let mut writer = WriterBuilder::new()
+                .has_headers(true)
+                .from_writer(&mut output);
+
+            for entry in entries {
+                writer.serialize(entry)?;
+            }
+
+            writer.flush()?;
+        }
+        StatementFormat::Jsonl => {
+            for entry in entries {
+                serde_json::to_writer(&mut output, entry)?;
+                output.write_all(b"\n")?;
+            }
+
+            output.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::RoundingStrategy;
+    use rust_decimal_macros::dec;
+
+    fn entries() -> Vec<HistoryEntry> {
+        vec![HistoryEntry {
+            seq: Some(1),
+            client: 1,
+            tx: 1,
+            transaction_type: TransactionType::Deposit,
+            currency: "USD".to_string(),
+            available_before: dec!(0),
+            held_before: dec!(0),
+            total_before: dec!(0),
+            available_after: dec!(10.5),
+            held_after: dec!(0),
+            total_after: dec!(10.5),
+            status_before: None,
+            status_after: Some(TransactionStatus::Normal),
+            locked: false,
+            leg: None,
+            precision: Precision::default(),
+        }, HistoryEntry {
+            seq: Some(2),
+            client: 1,
+            tx: 1,
+            transaction_type: TransactionType::Dispute,
+            currency: "USD".to_string(),
+            available_before: dec!(10.5),
+            held_before: dec!(0),
+            total_before: dec!(10.5),
+            available_after: dec!(0),
+            held_after: dec!(10.5),
+            total_after: dec!(10.5),
+            status_before: Some(TransactionStatus::Normal),
+            status_after: Some(TransactionStatus::Disputed),
+            locked: false,
+            leg: None,
+            precision: Precision::default(),
+        }]
+    }
+
+    fn statement_to_string(format: StatementFormat) -> String {
+        let mut output = Vec::new();
+        write_statement(&entries(), format, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_write_statement_csv() {
+        assert_eq!(
+            statement_to_string(StatementFormat::Csv),
+            "seq,client,tx,type,currency,available_before,held_before,total_before,available_after,held_after,total_after,status_before,status_after,locked\n\
+            1,1,1,deposit,USD,0,0,0,10.5,0,10.5,,normal,false\n\
+            2,1,1,dispute,USD,10.5,0,10.5,0,10.5,10.5,normal,disputed,false\n"
+        );
+    }
+
+    #[test]
+    fn test_write_statement_jsonl() {
+        assert_eq!(
+            statement_to_string(StatementFormat::Jsonl),
+            "{\"seq\":1,\"client\":1,\"tx\":1,\"type\":\"deposit\",\"currency\":\"USD\",\"available_before\":\"0\",\"held_before\":\"0\",\"total_before\":\"0\",\"available_after\":\"10.5\",\"held_after\":\"0\",\"total_after\":\"10.5\",\"status_before\":null,\"status_after\":\"normal\",\"locked\":false}\n\
+            {\"seq\":2,\"client\":1,\"tx\":1,\"type\":\"dispute\",\"currency\":\"USD\",\"available_before\":\"10.5\",\"held_before\":\"0\",\"total_before\":\"10.5\",\"available_after\":\"0\",\"held_after\":\"10.5\",\"total_after\":\"10.5\",\"status_before\":\"normal\",\"status_after\":\"disputed\",\"locked\":false}\n"
+        );
+    }
+
+    #[test]
+    fn test_statement_amounts_are_rounded_to_the_precision() {
+        let mut entry = entries().remove(0);
+        entry.available_after = dec!(10.123456789);
+        entry.total_after = dec!(10.123456789);
+
+        let mut output = Vec::new();
+        write_statement(&[entry.clone()], StatementFormat::Csv, &mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().ends_with(",10.1234,0,10.1234,,normal,false\n"));
+
+        entry.precision = Precision::new(0, RoundingStrategy::ToZero);
+        let mut output = Vec::new();
+        write_statement(&[entry], StatementFormat::Csv, &mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().ends_with(",10,0,10,,normal,false\n"));
+    }
+
+    #[test]
+    fn test_write_statement_empty() {
+        let mut output = Vec::new();
+        write_statement(&[], StatementFormat::Csv, &mut output).unwrap();
+        assert!(output.is_empty());
+    }
+}
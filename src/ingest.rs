@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::io;
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinSet;
+
+use crate::payment_engine::{PaymentEngine, PendingOutcome};
+use crate::shutdown::{self, requested, ShutdownReceiver};
+use crate::transaction::{OutcomeStatus, TransactionEntity};
+
+/// Frames are a big-endian u32 length followed by that many bytes of JSON
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+// Transactions of a connection which are submitted but not acknowledged yet
+const PIPELINE_SIZE: usize = 1024;
+
+/// Reads one frame, `None` when the stream is closed between frames
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let length = match reader.read_u32().await {
+        Ok(length) => length as usize,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Frame of {} bytes is too large", length)));
+    }
+
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(payload))
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Frame of {} bytes is too large", payload.len())));
+    }
+
+    writer.write_u32(payload.len() as u32).await?;
+    writer.write_all(payload).await
+}
+
+// Acknowledgement of a frame which could not be decoded into a transaction
+#[derive(Debug, Serialize)]
+struct InvalidFrame {
+    status: OutcomeStatus,
+    error: String,
+}
+
+enum Ack {
+    Pending(PendingOutcome),
+    Invalid(String),
+}
+
+/// Accepts producers on the listener, every frame is acknowledged with the outcome of the transaction in the
+/// order the frames were received. Transactions go to the account workers like `process_transaction` ones.
+pub async fn serve_ingest(listener: TcpListener, engine: Arc<Mutex<PaymentEngine>>) -> Result<(), Box<dyn Error>> {
+    serve_ingest_until(listener, engine, shutdown::never()).await
+}
+
+/// Stops accepting producers and reading frames once a stop is requested, returns after the frames already read
+/// are acknowledged
+pub async fn serve_ingest_until(listener: TcpListener, engine: Arc<Mutex<PaymentEngine>>, shutdown: ShutdownReceiver) -> Result<(), Box<dyn Error>> {
+    let mut connections = JoinSet::new();
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = requested(shutdown.clone()) => break,
+        };
+        let engine = engine.clone();
+        let shutdown = shutdown.clone();
+
+        connections.spawn(async move {
+            if let Err(e) = handle_connection(stream, engine, shutdown).await {
+                eprintln!("Ingestion connection {} failed: {}", peer, e);
+            }
+        });
+
+        // Finished connections are dropped, so the set doesn't grow with every producer
+        while connections.try_join_next().is_some() {}
+    }
+
+    // The connections stop reading on the same signal, the engine may be shut down once their acks are written
+    while connections.join_next().await.is_some() {}
+    Ok(())
+}
+
+async fn handle_connection(stream: TcpStream, engine: Arc<Mutex<PaymentEngine>>, shutdown: ShutdownReceiver) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let (ack_tx, ack_rx) = mpsc::channel(PIPELINE_SIZE);
+
+    // The producer keeps sending while the outcomes of the earlier transactions are awaited here
+    let acks = tokio::spawn(write_acks(BufWriter::new(writer), ack_rx));
+
+    loop {
+        let payload = tokio::select! {
+            payload = read_frame(&mut reader) => payload?,
+            _ = requested(shutdown.clone()) => None,
+        };
+        let payload = match payload {
+            Some(payload) => payload,
+            None => break,
+        };
+
+        let ack = match serde_json::from_slice::<TransactionEntity>(&payload) {
+            Ok(entity) => match engine.lock().await.submit_transaction(entity).await {
+                Ok(pending) => Ack::Pending(pending),
+                Err(e) => Ack::Invalid(e.to_string()),
+            },
+            Err(e) => Ack::Invalid(format!("Error deserializing transaction: {}", e)),
+        };
+
+        if ack_tx.send(ack).await.is_err() {
+            break;
+        }
+    }
+
+    drop(ack_tx);
+    acks.await??;
+    Ok(())
+}
+
+async fn write_acks<W: AsyncWrite + Unpin>(mut writer: W, mut acks: mpsc::Receiver<Ack>) -> io::Result<()> {
+    while let Some(ack) = acks.recv().await {
+        let payload = match ack {
+            Ack::Pending(pending) => serde_json::to_vec(&pending.outcome().await)?,
+            Ack::Invalid(error) => serde_json::to_vec(&InvalidFrame { status: OutcomeStatus::Rejected, error })?,
+        };
+        write_frame(&mut writer, &payload).await?;
+
+        // Flush only when nothing else is ready, so the acknowledgements of a burst go out together
+        if acks.is_empty() {
+            writer.flush().await?;
+        }
+    }
+
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frame_roundtrip() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, b"{}").await.unwrap();
+        write_frame(&mut buffer, b"").await.unwrap();
+        assert_eq!(&buffer[..6], &[0, 0, 0, 2, b'{', b'}']);
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(b"{}".to_vec()));
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(Vec::new()));
+        assert_eq!(read_frame(&mut reader).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_frame_too_large() {
+        let mut reader: &[u8] = &[0, 1, 0, 1];
+        assert!(read_frame(&mut reader).await.is_err());
+
+        let mut truncated: &[u8] = &[0, 0, 0, 5, b'{'];
+        assert!(read_frame(&mut truncated).await.is_err());
+    }
+}
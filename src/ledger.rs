@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use rust_decimal::Decimal;
+
+use crate::currency::Currency;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LedgerAccount {
+    ClientAvailable(u16),
+    ClientHeld(u16),
+    // Counterparty of the money entering or leaving the system through deposits and withdrawals
+    Settlement,
+    ChargebackLoss,
+    FeeIncome,
+    // Funds debited from the source of a transfer and not yet credited to the destination
+    TransfersInTransit,
+}
+
+/// Balanced entry, `amount` is credited to `to` and debited from `from`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+    pub tx: u32,
+    pub currency: Currency,
+    pub from: LedgerAccount,
+    pub to: LedgerAccount,
+    pub amount: Decimal,
+}
+
+#[derive(Debug, Default)]
+pub struct Ledger {
+    postings: Vec<Posting>,
+    balances: HashMap<(LedgerAccount, Currency), Decimal>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn post(&mut self, tx: u32, currency: &str, from: LedgerAccount, to: LedgerAccount, amount: Decimal) {
+        *self.balances.entry((from, currency.to_string())).or_default() -= amount;
+        *self.balances.entry((to, currency.to_string())).or_default() += amount;
+        self.postings.push(Posting { tx, currency: currency.to_string(), from, to, amount });
+    }
+
+    pub fn postings(&self) -> &[Posting] {
+        &self.postings
+    }
+
+    pub fn balances(&self) -> &HashMap<(LedgerAccount, Currency), Decimal> {
+        &self.balances
+    }
+
+    pub fn balance(&self, account: LedgerAccount, currency: &str) -> Decimal {
+        self.balances.get(&(account, currency.to_string())).copied().unwrap_or_default()
+    }
+
+    // One-sided entry, which `post` never makes
+    #[cfg(test)]
+    pub(crate) fn post_unbalanced(&mut self, currency: &str, to: LedgerAccount, amount: Decimal) {
+        *self.balances.entry((to, currency.to_string())).or_default() += amount;
+    }
+}
+
+/// Sums the balances of several ledgers
+pub fn merge_balances<'a, I>(ledgers: I) -> HashMap<(LedgerAccount, Currency), Decimal>
+where
+    I: IntoIterator<Item = &'a Ledger>,
+{
+    let mut balances: HashMap<(LedgerAccount, Currency), Decimal> = HashMap::new();
+
+    for ledger in ledgers {
+        for (key, balance) in ledger.balances() {
+            *balances.entry(key.clone()).or_default() += *balance;
+        }
+    }
+
+    balances
+}
+
+/// Sums of the client accounts in one currency, the ledger is checked against them
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AccountTotals {
+    pub total: Decimal,
+    pub fees: Decimal,
+}
+
+/// Checks the merged ledger: the balances of every currency sum to zero, the client ledger accounts hold the account
+/// totals, the fee income equals the fees charged to the accounts and no transfer is left in transit
+pub fn verify_totals(balances: &HashMap<(LedgerAccount, Currency), Decimal>, accounts: &HashMap<Currency, AccountTotals>) -> Result<(), Box<dyn Error>> {
+    let mut sums: HashMap<&Currency, Decimal> = HashMap::new();
+    for ((_, currency), balance) in balances.iter() {
+        *sums.entry(currency).or_default() += *balance;
+    }
+
+    for (currency, sum) in sums {
+        if !sum.is_zero() {
+            return Err(format!("Ledger balances of {} sum to {} instead of zero", currency, sum).into());
+        }
+    }
+
+    let mut ledger: HashMap<&Currency, AccountTotals> = accounts.keys().map(|currency| (currency, AccountTotals::default())).collect();
+    let mut in_transit: HashMap<&Currency, Decimal> = HashMap::new();
+
+    for ((account, currency), balance) in balances.iter() {
+        let totals = ledger.entry(currency).or_default();
+        match account {
+            LedgerAccount::ClientAvailable(_) | LedgerAccount::ClientHeld(_) => totals.total += *balance,
+            LedgerAccount::FeeIncome => totals.fees += *balance,
+            LedgerAccount::TransfersInTransit => *in_transit.entry(currency).or_default() += *balance,
+            LedgerAccount::Settlement | LedgerAccount::ChargebackLoss => {}
+        }
+    }
+
+    for (currency, totals) in ledger {
+        let expected = accounts.get(currency).copied().unwrap_or_default();
+        if totals.total != expected.total {
+            return Err(format!("Ledger holds {} {} for the clients, the accounts total {}", totals.total, currency, expected.total).into());
+        }
+
+        if totals.fees != expected.fees {
+            return Err(format!("Ledger fee income {} {} does not match the fees charged to the accounts {}", totals.fees, currency, expected.fees).into());
+        }
+    }
+
+    for (currency, amount) in in_transit {
+        if !amount.is_zero() {
+            return Err(format!("Ledger has {} {} of transfers in transit", amount, currency).into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_post_moves_balance() {
+        let mut ledger = Ledger::new();
+        ledger.post(1, "USD", LedgerAccount::Settlement, LedgerAccount::ClientAvailable(1), dec!(10.0));
+        ledger.post(1, "USD", LedgerAccount::ClientAvailable(1), LedgerAccount::ClientHeld(1), dec!(4.0));
+        ledger.post(2, "EUR", LedgerAccount::Settlement, LedgerAccount::ClientAvailable(1), dec!(3.0));
+
+        assert_eq!(ledger.balance(LedgerAccount::Settlement, "USD"), dec!(-10.0));
+        assert_eq!(ledger.balance(LedgerAccount::ClientAvailable(1), "USD"), dec!(6.0));
+        assert_eq!(ledger.balance(LedgerAccount::ClientHeld(1), "USD"), dec!(4.0));
+        assert_eq!(ledger.balance(LedgerAccount::ChargebackLoss, "USD"), dec!(0));
+        assert_eq!(ledger.balance(LedgerAccount::ClientAvailable(1), "EUR"), dec!(3.0));
+        assert_eq!(ledger.postings().len(), 3);
+    }
+
+    fn totals(total: Decimal, fees: Decimal) -> HashMap<Currency, AccountTotals> {
+        HashMap::from([("USD".to_string(), AccountTotals { total, fees })])
+    }
+
+    #[test]
+    fn test_merge_balances() {
+        let mut first = Ledger::new();
+        first.post(1, "USD", LedgerAccount::Settlement, LedgerAccount::ClientAvailable(1), dec!(10.0));
+        let mut second = Ledger::new();
+        second.post(2, "USD", LedgerAccount::Settlement, LedgerAccount::ClientAvailable(2), dec!(5.0));
+        second.post(3, "USD", LedgerAccount::ClientAvailable(2), LedgerAccount::FeeIncome, dec!(1.0));
+
+        let balances = merge_balances([&first, &second]);
+        assert_eq!(balances[&(LedgerAccount::Settlement, "USD".to_string())], dec!(-15.0));
+        assert_eq!(balances[&(LedgerAccount::ClientAvailable(2), "USD".to_string())], dec!(4.0));
+        verify_totals(&balances, &totals(dec!(14.0), dec!(1.0))).unwrap();
+    }
+
+    #[test]
+    fn test_verify_totals_unbalanced() {
+        let mut ledger = Ledger::new();
+        ledger.post(1, "USD", LedgerAccount::Settlement, LedgerAccount::ClientAvailable(1), dec!(10.0));
+        ledger.balances.insert((LedgerAccount::ClientHeld(1), "USD".to_string()), dec!(1.0));
+
+        let err = verify_totals(&merge_balances([&ledger]), &totals(dec!(10.0), dec!(0))).unwrap_err();
+        assert_eq!(err.to_string(), "Ledger balances of USD sum to 1.0 instead of zero");
+    }
+
+    #[test]
+    fn test_verify_totals_against_accounts() {
+        let mut ledger = Ledger::new();
+        ledger.post(1, "USD", LedgerAccount::Settlement, LedgerAccount::ClientAvailable(1), dec!(10.0));
+        ledger.post(2, "USD", LedgerAccount::Settlement, LedgerAccount::ClientHeld(1), dec!(1.0));
+
+        let err = verify_totals(&merge_balances([&ledger]), &totals(dec!(10.0), dec!(0))).unwrap_err();
+        assert_eq!(err.to_string(), "Ledger holds 11.0 USD for the clients, the accounts total 10.0");
+    }
+
+    #[test]
+    fn test_verify_totals_fees_and_transit() {
+        let mut ledger = Ledger::new();
+        ledger.post(1, "USD", LedgerAccount::Settlement, LedgerAccount::ClientAvailable(1), dec!(10.0));
+        ledger.post(2, "USD", LedgerAccount::ClientAvailable(1), LedgerAccount::FeeIncome, dec!(1.0));
+        let balances = merge_balances([&ledger]);
+        assert!(verify_totals(&balances, &totals(dec!(9.0), dec!(0.5))).unwrap_err().to_string().contains("fee income"));
+
+        ledger.post(3, "USD", LedgerAccount::ClientAvailable(1), LedgerAccount::TransfersInTransit, dec!(2.0));
+        let err = verify_totals(&merge_balances([&ledger]), &totals(dec!(7.0), dec!(1.0))).unwrap_err();
+        assert_eq!(err.to_string(), "Ledger has 2.0 USD of transfers in transit");
+    }
+}
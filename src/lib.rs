@@ -1,49 +1,297 @@
 pub mod transaction;
-mod decimal;
+pub mod decimal;
 pub mod account;
+#[cfg(feature = "columnar")]
+pub mod columnar;
+pub mod compression;
 pub mod payment_engine;
+pub mod config;
+pub mod credit;
+pub mod currency;
+pub mod fast_csv;
+pub mod fees;
+pub mod generator;
+pub mod history;
+pub mod ingest;
+pub mod ledger;
+pub mod limits;
+pub mod parallel_csv;
+pub mod precision;
+pub mod server;
+pub mod shutdown;
 
-use std::{error::Error, io::{Read, Write}};
+use std::{collections::VecDeque, error::Error, path::Path, sync::Arc, io::{BufRead, BufReader, Read, Write}};
 
-use csv::{ReaderBuilder, WriterBuilder};
+use compression::Encoder;
+use config::{AppConfig, InputFormat, ParallelParsing};
+use precision::{PrecisionConfig, PrecisionPolicy};
+use shutdown::{ShutdownReceiver, Signal};
+use csv::{ByteRecord, ReaderBuilder, WriterBuilder};
+use fast_csv::RecordParser;
+use parallel_csv::{parse_chunk, ChunkReader};
 use payment_engine::PaymentEngine;
 use transaction::TransactionEntity;
 
 
 pub struct App {}
 
+/// How a run ended, the output is written in both cases
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunStatus {
+    Completed,
+    /// Intake stopped by the signal, the transactions read before it are applied
+    Interrupted(Signal),
+}
+
 impl App {
-    pub async fn run<R: Read, W: Write>(input: R, mut output: W, ordeded_output: bool) -> Result<(), Box<dyn Error>> {
-        let mut reader = ReaderBuilder::new()
-            .has_headers(true)
-            .trim(csv::Trim::All)
-            .flexible(true)
-            .from_reader(input);
+    pub async fn run<R: Read, W: Write>(input: R, output: W, ordeded_output: bool) -> Result<(), Box<dyn Error>> {
+        let config = AppConfig {
+            ordered_output: ordeded_output,
+            ..Default::default()
+        };
 
-        let mut engine = PaymentEngine::new();
+        Self::run_with_config(input, output, config).await
+    }
 
-        for result in reader.deserialize::<TransactionEntity>() {
-            match result {
-                Ok(transaction) => engine.process_transaction(transaction).await?,
-                Err(err) => eprintln!("Error deserializing transaction: {}", err),
-            }
-        }
+    pub async fn run_with_config<R: Read, W: Write>(input: R, output: W, config: AppConfig) -> Result<(), Box<dyn Error>> {
+        Self::run_until(input, output, config, shutdown::never()).await?;
+        Ok(())
+    }
+
+    /// Processes the input until it ends or a stop is requested, in both cases the workers are drained and
+    /// the accounts (and the snapshot if configured) are written. Gzip and zstd inputs are decompressed.
+    pub async fn run_until<R: Read, W: Write>(input: R, output: W, config: AppConfig, shutdown: ShutdownReceiver) -> Result<RunStatus, Box<dyn Error>> {
+        let input = compression::decompress(input, config.input_compression)?;
+        let intake = Intake {
+            precision: config.engine.precision.clone(),
+            shutdown,
+        };
+        #[cfg(feature = "columnar")]
+        let columnar_scale = config.engine.precision.max_scale();
+        let mut engine = PaymentEngine::with_config(config.engine);
+
+        // The currency column of the output mirrors the input
+        let (status, with_currency) = match (config.input_format, config.parallel_parsing) {
+            (InputFormat::Csv, None) => intake.read_csv(input, &mut engine).await?,
+            (InputFormat::Csv, Some(parallel)) => intake.read_csv_parallel(input, parallel, &mut engine).await?,
+            (InputFormat::Jsonl, _) => intake.read_jsonl(input, &mut engine).await?,
+        };
 
-        engine.shutdown().await;
+        // A failed ledger verification is reported after the accounts are written, so the output is not lost.
+        // Kept as a string, a boxed error would make the future not `Send`.
+        let verified = engine.shutdown().await.map_err(|e| e.to_string());
+
+        for client in engine.faulted_clients().await {
+            eprintln!("Account {} is faulted after its worker panicked, its later transactions were rejected", client);
+        }
 
-        let accounts = engine.get_account_entities(ordeded_output).await;
+        let mut output = Encoder::new(output, config.output_compression)?;
+        let accounts = engine.get_account_entities(config.ordered_output).await;
         
         let mut writer = WriterBuilder::new()
             .has_headers(true)
             .from_writer(&mut output);
 
-        for account in accounts {
+        for mut account in accounts {
+            if !with_currency {
+                account.currency = None;
+            }
+
             if let Err(err) = writer.serialize(account) {
                 eprintln!("Error serializing account: {}", err);
             }
         }
 
         writer.flush()?;
-        Ok(())
+        drop(writer);
+        output.finish()?;
+
+        // The snapshot goes after the accounts output, a failed one is reported like the ledger verification
+        let snapshot = match config.snapshot.as_ref() {
+            Some(path) => write_snapshot_file(&engine, path).await.map_err(|e| e.to_string()),
+            None => Ok(()),
+        };
+        verified?;
+        snapshot?;
+
+        // Written after the CSV output, so a failed columnar file doesn't lose it
+        #[cfg(feature = "columnar")]
+        if let Some(columnar) = config.columnar.as_ref() {
+            if let Some(path) = columnar.accounts.as_ref() {
+                columnar::write_file(&columnar::accounts_batch(&engine.get_account_entities(true).await, columnar_scale)?, path)?;
+            }
+            if let Some(path) = columnar.history.as_ref() {
+                columnar::write_file(&columnar::history_batch(&engine.history_entries().await?, columnar_scale)?, path)?;
+            }
+        }
+
+        Ok(status)
+    }
+}
+
+async fn write_snapshot_file(engine: &PaymentEngine, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut file = compression::create(path)?;
+    engine.write_snapshot(&mut file).await?;
+    file.finish()?;
+    Ok(())
+}
+
+// Reads the input rows and hands the accepted ones to the engine, returns how the reading ended and whether
+// the input had currencies
+struct Intake {
+    precision: PrecisionConfig,
+    shutdown: ShutdownReceiver,
+}
+
+impl Intake {
+    async fn read_csv<R: Read>(&self, input: R, engine: &mut PaymentEngine) -> Result<(RunStatus, bool), Box<dyn Error>> {
+        // The fields are trimmed by the parser, trimming the whole record in the reader would copy it
+        let mut reader = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::Headers)
+            .flexible(true)
+            .from_reader(input);
+
+        let headers = reader.headers()?.clone();
+        let with_currency = headers.iter().any(|header| header == "currency");
+
+        let parser = RecordParser::new(&headers);
+        let mut record = ByteRecord::new();
+        loop {
+            match reader.read_byte_record(&mut record) {
+                Ok(true) => {}
+                Ok(false) => break,
+                // Only a failed read of the input stops the run, malformed rows are logged and skipped
+                Err(err) if err.is_io_error() => return Err(err.into()),
+                Err(err) => {
+                    eprintln!("Error reading transaction: {}", err);
+                    continue;
+                }
+            }
+
+            if let Some(signal) = *self.shutdown.borrow() {
+                return Ok((RunStatus::Interrupted(signal), with_currency));
+            }
+
+            let line = record.position().map(|position| position.line()).unwrap_or_default();
+
+            match parser.parse(&record).and_then(|row| row) {
+                Ok(transaction) => self.submit(transaction, line, engine).await?,
+                Err(err) => eprintln!("Error deserializing transaction on line {}: {}", line, err),
+            }
+        }
+
+        Ok((RunStatus::Completed, with_currency))
+    }
+
+    // Chunks are parsed on the blocking threads while the rows of the earlier ones are submitted. The rows go to the
+    // engine in the input order, so the clients get their transactions in the same order as with `read_csv`.
+    async fn read_csv_parallel<R: Read>(&self, input: R, parallel: ParallelParsing, engine: &mut PaymentEngine) -> Result<(RunStatus, bool), Box<dyn Error>> {
+        let mut chunks = ChunkReader::new(input, parallel.chunk_size);
+        let headers = chunks.headers()?;
+        let with_currency = headers.iter().any(|header| header == "currency");
+        let parser = Arc::new(RecordParser::new(&headers));
+
+        let mut in_flight = VecDeque::new();
+        loop {
+            while in_flight.len() < parallel.threads.max(1) {
+                let Some(chunk) = chunks.next_chunk()? else {
+                    break;
+                };
+
+                let parser = parser.clone();
+                in_flight.push_back(tokio::task::spawn_blocking(move || parse_chunk(chunk, &parser)));
+            }
+
+            let Some(parsing) = in_flight.pop_front() else {
+                break;
+            };
+
+            for (line, row) in parsing.await?? {
+                if let Some(signal) = *self.shutdown.borrow() {
+                    return Ok((RunStatus::Interrupted(signal), with_currency));
+                }
+
+                match row {
+                    Ok(transaction) => self.submit(transaction, line, engine).await?,
+                    Err(err) => eprintln!("Error deserializing transaction on line {}: {}", line, err),
+                }
+            }
+        }
+
+        Ok((RunStatus::Completed, with_currency))
+    }
+
+    // One JSON transaction per line, blank lines are skipped
+    async fn read_jsonl<R: Read>(&self, input: R, engine: &mut PaymentEngine) -> Result<(RunStatus, bool), Box<dyn Error>> {
+        let mut with_currency = false;
+
+        for (index, row) in BufReader::new(input).lines().enumerate() {
+            if let Some(signal) = *self.shutdown.borrow() {
+                return Ok((RunStatus::Interrupted(signal), with_currency));
+            }
+
+            let row = row?;
+            let line = index as u64 + 1;
+            if row.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str::<TransactionEntity>(&row) {
+                Ok(transaction) => {
+                    with_currency |= transaction.currency.is_some();
+                    self.submit(transaction, line, engine).await?;
+                }
+                Err(err) => eprintln!("Error deserializing transaction on line {}: {}", line, err),
+            }
+        }
+
+        Ok((RunStatus::Completed, with_currency))
+    }
+
+    async fn submit(&self, transaction: TransactionEntity, line: u64, engine: &mut PaymentEngine) -> Result<(), Box<dyn Error>> {
+        match self.check_precision(&transaction, line) {
+            Ok(()) => engine.process_transaction(transaction).await,
+            Err(err) => {
+                eprintln!("{}", err);
+                Ok(())
+            }
+        }
+    }
+
+    // With the reject policy (`--strict`) over-precise amounts are rejected here, where the input line is known.
+    // The engine would reject them as well, but only with the sequence number.
+    fn check_precision(&self, transaction: &TransactionEntity, line: u64) -> Result<(), String> {
+        match transaction.amount {
+            Some(amount) if self.precision.policy == PrecisionPolicy::Reject => self.precision
+                .apply(amount, transaction.currency())
+                .map(|_| ())
+                .map_err(|e| format!("Error on line {}: {}", line, e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_strict_precision_names_the_line() {
+        let intake = Intake {
+            precision: PrecisionConfig { policy: PrecisionPolicy::Reject, ..Default::default() },
+            shutdown: shutdown::never(),
+        };
+        let transaction = TransactionEntity::new(transaction::TransactionType::Deposit, 1, 1, Some(dec!(1.23456789)));
+
+        assert_eq!(
+            intake.check_precision(&transaction, 5).unwrap_err(),
+            "Error on line 5: Amount 1.23456789 has more than 4 fractional digits allowed for USD"
+        );
+        assert!(intake.check_precision(&TransactionEntity { amount: Some(dec!(1.2345)), ..transaction.clone() }, 5).is_ok());
+
+        let lenient = Intake { precision: PrecisionConfig::default(), shutdown: shutdown::never() };
+        assert!(lenient.check_precision(&transaction, 5).is_ok());
     }
 }
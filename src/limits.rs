@@ -0,0 +1,280 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+
+use crate::currency::Currency;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    /// Last N applied transactions of the client in the currency, of any type
+    Transactions(usize),
+    /// Transactions with timestamps within the duration before the current one
+    Period(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WithdrawalWindow {
+    pub max_total: Decimal,
+    pub window: Window,
+}
+
+/// Risk limits checked before a transaction is applied, transfers out count as withdrawals
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub max_deposit: Option<Decimal>,
+    pub max_withdrawal: Option<Decimal>,
+    pub withdrawal_window: Option<WithdrawalWindow>,
+}
+
+impl Limits {
+    /// Fields set in `other` take precedence
+    pub fn merge(&self, other: &Limits) -> Limits {
+        Limits {
+            max_deposit: other.max_deposit.or(self.max_deposit),
+            max_withdrawal: other.max_withdrawal.or(self.max_withdrawal),
+            withdrawal_window: other.withdrawal_window.or(self.withdrawal_window),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Limits::default()
+    }
+}
+
+/// Limits per currency, the amounts of different currencies are never compared
+#[derive(Debug, Clone, Default)]
+pub struct LimitsConfig {
+    pub global: HashMap<Currency, Limits>,
+    pub clients: HashMap<u16, HashMap<Currency, Limits>>,
+}
+
+impl LimitsConfig {
+    /// Limits of the client in every currency which has global or client limits
+    pub fn for_client(&self, client: u16) -> HashMap<Currency, Limits> {
+        let mut limits = self.global.clone();
+        for (currency, client_limits) in self.clients.get(&client).into_iter().flatten() {
+            let merged = limits.get(currency).cloned().unwrap_or_default().merge(client_limits);
+            limits.insert(currency.clone(), merged);
+        }
+
+        limits
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitRule {
+    MaxDeposit,
+    MaxWithdrawal,
+    WithdrawalWindow,
+}
+
+impl fmt::Display for LimitRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitRule::MaxDeposit => write!(f, "max deposit"),
+            LimitRule::MaxWithdrawal => write!(f, "max withdrawal"),
+            LimitRule::WithdrawalWindow => write!(f, "withdrawal window"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitExceeded {
+    pub rule: LimitRule,
+    pub limit: Decimal,
+    pub attempted: Decimal,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LimitExceeded: {} limit is {}, attempted {}", self.rule, self.limit, self.attempted)
+    }
+}
+
+impl Error for LimitExceeded {}
+
+/// Per-account state of the limits, which are resolved once for the client
+#[derive(Debug, Default)]
+pub struct LimitTracker {
+    limits: HashMap<Currency, Limits>,
+    // Timestamp and withdrawn amount of every recent applied transaction of the currency, zero for the other types
+    recent: HashMap<Currency, VecDeque<(Option<u64>, Decimal)>>,
+}
+
+impl LimitTracker {
+    pub fn new(limits: HashMap<Currency, Limits>) -> Self {
+        LimitTracker {
+            limits,
+            recent: HashMap::new(),
+        }
+    }
+
+    pub fn check_deposit(&self, currency: &str, amount: Decimal) -> Result<(), LimitExceeded> {
+        match self.limits.get(currency).and_then(|limits| limits.max_deposit) {
+            Some(limit) if amount > limit => Err(LimitExceeded { rule: LimitRule::MaxDeposit, limit, attempted: amount }),
+            _ => Ok(()),
+        }
+    }
+
+    /// `timestamp` is the time of the transaction, used by the period windows
+    pub fn check_withdrawal(&self, currency: &str, amount: Decimal, timestamp: Option<u64>) -> Result<(), LimitExceeded> {
+        let Some(limits) = self.limits.get(currency) else {
+            return Ok(());
+        };
+
+        if let Some(limit) = limits.max_withdrawal {
+            if amount > limit {
+                return Err(LimitExceeded { rule: LimitRule::MaxWithdrawal, limit, attempted: amount });
+            }
+        }
+
+        if let Some(window) = limits.withdrawal_window {
+            let recent = self.recent.get(currency).into_iter().flatten();
+            let previous: Decimal = match window.window {
+                Window::Transactions(size) => recent.rev().take(size.saturating_sub(1)).map(|(_, withdrawn)| *withdrawn).sum(),
+                Window::Period(period) => recent
+                    .filter(|(recorded, _)| within(*recorded, timestamp, period))
+                    .map(|(_, withdrawn)| *withdrawn)
+                    .sum(),
+            };
+
+            if previous + amount > window.max_total {
+                return Err(LimitExceeded { rule: LimitRule::WithdrawalWindow, limit: window.max_total, attempted: previous + amount });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records an applied transaction, `withdrawn` is zero for everything except outflows
+    pub fn record(&mut self, currency: &str, withdrawn: Decimal, timestamp: Option<u64>) {
+        let window = match self.limits.get(currency).and_then(|limits| limits.withdrawal_window) {
+            Some(window) => window.window,
+            None => return,
+        };
+
+        let recent = self.recent.entry(currency.to_string()).or_default();
+        recent.push_back((timestamp, withdrawn));
+
+        match window {
+            Window::Transactions(size) => {
+                while recent.len() > size {
+                    recent.pop_front();
+                }
+            }
+            Window::Period(period) => {
+                while let Some((recorded, _)) = recent.front() {
+                    if within(*recorded, timestamp, period) {
+                        break;
+                    }
+                    recent.pop_front();
+                }
+            }
+        }
+    }
+}
+
+// Transactions without timestamps are kept in the window, there is no way to tell their age
+fn within(recorded: Option<u64>, now: Option<u64>, period: Duration) -> bool {
+    match (recorded, now) {
+        (Some(recorded), Some(now)) => now.saturating_sub(recorded) < period.as_secs(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn usd(limits: Limits) -> HashMap<Currency, Limits> {
+        HashMap::from([("USD".to_string(), limits)])
+    }
+
+    #[test]
+    fn test_client_limits_override_global() {
+        let config = LimitsConfig {
+            global: usd(Limits { max_deposit: Some(dec!(100)), max_withdrawal: Some(dec!(50)), ..Default::default() }),
+            clients: HashMap::from([(2, HashMap::from([
+                ("USD".to_string(), Limits { max_withdrawal: Some(dec!(500)), ..Default::default() }),
+                ("EUR".to_string(), Limits { max_deposit: Some(dec!(10)), ..Default::default() }),
+            ]))]),
+        };
+
+        assert_eq!(config.for_client(1)["USD"].max_withdrawal, Some(dec!(50)));
+        assert!(!config.for_client(1).contains_key("EUR"));
+        assert_eq!(config.for_client(2)["USD"].max_withdrawal, Some(dec!(500)));
+        assert_eq!(config.for_client(2)["USD"].max_deposit, Some(dec!(100)));
+        assert_eq!(config.for_client(2)["EUR"], Limits { max_deposit: Some(dec!(10)), ..Default::default() });
+        assert!(LimitsConfig::default().for_client(1).is_empty());
+    }
+
+    #[test]
+    fn test_single_transaction_limits() {
+        let tracker = LimitTracker::new(usd(Limits { max_deposit: Some(dec!(100)), max_withdrawal: Some(dec!(50)), ..Default::default() }));
+
+        assert!(tracker.check_deposit("USD", dec!(100)).is_ok());
+        assert_eq!(tracker.check_deposit("USD", dec!(100.01)).unwrap_err().rule, LimitRule::MaxDeposit);
+        assert!(tracker.check_withdrawal("USD", dec!(50), None).is_ok());
+        assert_eq!(
+            tracker.check_withdrawal("USD", dec!(51), None).unwrap_err(),
+            LimitExceeded { rule: LimitRule::MaxWithdrawal, limit: dec!(50), attempted: dec!(51) }
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_window() {
+        let window = WithdrawalWindow { max_total: dec!(100), window: Window::Transactions(3) };
+        let mut tracker = LimitTracker::new(usd(Limits { withdrawal_window: Some(window), ..Default::default() }));
+
+        tracker.record("USD", dec!(60), None);
+        tracker.record("USD", dec!(0), None);
+        assert_eq!(tracker.check_withdrawal("USD", dec!(41), None).unwrap_err().attempted, dec!(101));
+        assert!(tracker.check_withdrawal("USD", dec!(40), None).is_ok());
+
+        // The first withdrawal leaves the window
+        tracker.record("USD", dec!(0), None);
+        assert!(tracker.check_withdrawal("USD", dec!(100), None).is_ok());
+    }
+
+    #[test]
+    fn test_withdrawal_period_window() {
+        let window = WithdrawalWindow { max_total: dec!(100), window: Window::Period(Duration::from_secs(3600)) };
+        let mut tracker = LimitTracker::new(usd(Limits { withdrawal_window: Some(window), ..Default::default() }));
+
+        tracker.record("USD", dec!(60), Some(1000));
+        tracker.record("USD", dec!(30), Some(2000));
+        assert!(tracker.check_withdrawal("USD", dec!(11), Some(3000)).is_err());
+        assert!(tracker.check_withdrawal("USD", dec!(10), Some(3000)).is_ok());
+
+        // An hour after the first withdrawal only the second one counts
+        assert!(tracker.check_withdrawal("USD", dec!(70), Some(4600)).is_ok());
+        tracker.record("USD", dec!(70), Some(4600));
+        assert_eq!(tracker.recent["USD"].len(), 2);
+        assert!(tracker.check_withdrawal("USD", dec!(1), Some(4601)).is_err());
+    }
+
+    #[test]
+    fn test_limits_per_currency() {
+        let window = WithdrawalWindow { max_total: dec!(100), window: Window::Transactions(3) };
+        let mut tracker = LimitTracker::new(HashMap::from([
+            ("USD".to_string(), Limits { max_deposit: Some(dec!(100)), withdrawal_window: Some(window), ..Default::default() }),
+            ("JPY".to_string(), Limits { max_deposit: Some(dec!(10000)), withdrawal_window: Some(window), ..Default::default() }),
+        ]));
+
+        assert!(tracker.check_deposit("JPY", dec!(5000)).is_ok());
+        assert!(tracker.check_deposit("USD", dec!(5000)).is_err());
+        assert!(tracker.check_deposit("EUR", dec!(5000)).is_ok());
+
+        // The withdrawals of one currency don't count in the window of the other
+        tracker.record("USD", dec!(90), None);
+        tracker.record("JPY", dec!(0), None);
+        tracker.record("JPY", dec!(0), None);
+        assert!(tracker.check_withdrawal("JPY", dec!(100), None).is_ok());
+        assert_eq!(tracker.check_withdrawal("USD", dec!(20), None).unwrap_err().attempted, dec!(110));
+        assert!(tracker.check_withdrawal("EUR", dec!(1000), None).is_ok());
+    }
+}
@@ -2,18 +2,80 @@ use std::fs::File;
 use std::io;
 use std::env;
 use std::error::Error;
-use payment_engine::App;
+use std::time::Duration;
+use std::path::PathBuf;
+use std::process;
+use payment_engine::{App, RunStatus};
+use payment_engine::compression::Compression;
+#[cfg(feature = "columnar")]
+use payment_engine::columnar::ColumnarOutput;
+use payment_engine::config::{AppConfig, EngineMode, InputFormat, ParallelParsing};
+use payment_engine::credit::load_credit_limits;
+use payment_engine::precision::PrecisionPolicy;
+use payment_engine::shutdown::listen_for_signals;
+
+const USAGE: &str = "Usage: cargo run -- <transactions_file> [--check-invariants] [--double-entry] [--strict] [--credit-limits <file>] [--dispute-window-days <days>] [--snapshot <file>] [--parse-threads <n>] [--shards <n>] [--compress-output <gzip|zstd>] [--columnar <file>] [--columnar-history <file>]";
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
-        return Err("Usage: cargo run -- <transactions_file>".into());
+        return Err(USAGE.into());
+    }
+
+    let mut config = AppConfig {
+        input_format: InputFormat::from_path(&args[1]),
+        input_compression: Compression::from_path(&args[1]),
+        ..Default::default()
+    };
+    let mut options = args[2..].iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--check-invariants" => config.engine.check_invariants = true,
+            "--double-entry" => config.engine.double_entry = true,
+            "--strict" => config.engine.precision.policy = PrecisionPolicy::Reject,
+            "--credit-limits" => {
+                let path = options.next().ok_or(USAGE)?;
+                config.engine.credit_limits = Some(load_credit_limits(File::open(path)?)?);
+            }
+            "--snapshot" => config.snapshot = Some(PathBuf::from(options.next().ok_or(USAGE)?)),
+            "--dispute-window-days" => {
+                let days: u64 = options.next().ok_or(USAGE)?.parse()?;
+                let seconds = days.checked_mul(24 * 60 * 60).ok_or(USAGE)?;
+                config.engine.dispute_window = Some(Duration::from_secs(seconds));
+            }
+            "--parse-threads" => {
+                let threads: usize = options.next().ok_or(USAGE)?.parse()?;
+                config.parallel_parsing = Some(ParallelParsing { threads, ..Default::default() });
+            }
+            "--compress-output" => config.output_compression = Some(options.next().ok_or(USAGE)?.parse()?),
+            #[cfg(feature = "columnar")]
+            "--columnar" => {
+                let path = PathBuf::from(options.next().ok_or(USAGE)?);
+                config.columnar.get_or_insert_with(ColumnarOutput::default).accounts = Some(path);
+            }
+            #[cfg(feature = "columnar")]
+            "--columnar-history" => {
+                let path = PathBuf::from(options.next().ok_or(USAGE)?);
+                config.columnar.get_or_insert_with(ColumnarOutput::default).history = Some(path);
+                config.engine.history = true;
+            }
+            #[cfg(not(feature = "columnar"))]
+            "--columnar" | "--columnar-history" => return Err("Columnar output needs the columnar feature, build with --features columnar".into()),
+            "--shards" => config.engine.mode = EngineMode::Sharded(options.next().ok_or(USAGE)?.parse()?),
+            _ => return Err(format!("Unknown option {}\n{}", arg, USAGE).into()),
+        }
     }
     
     let transactions_file = File::open(&args[1])?;
     let stdout = io::stdout();
-    App::run(transactions_file, stdout, false).await?;
+    let shutdown = listen_for_signals()?;
+
+    if let RunStatus::Interrupted(signal) = App::run_until(transactions_file, stdout, config, shutdown).await? {
+        eprintln!("Stopped by {}, the accounts are written for the transactions read before it", signal.name());
+        process::exit(signal.exit_code());
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,304 @@
+use std::io::{self, Cursor, Read, SeekFrom};
+
+use csv::{ByteRecord, Position, ReaderBuilder, StringRecord};
+
+use crate::fast_csv::RecordParser;
+use crate::transaction::TransactionEntity;
+
+/// Consecutive whole records of the input
+#[derive(Debug, PartialEq)]
+pub struct Chunk {
+    pub data: Vec<u8>,
+    /// Position of the first record in the input, so errors name the same lines as without chunks
+    pub line: u64,
+    pub byte: u64,
+}
+
+/// Row of a chunk with its input line, rows which are no transactions carry the error instead
+pub type ParsedRow = (u64, Result<TransactionEntity, String>);
+
+/// Splits the input into chunks at record boundaries, line breaks inside quoted fields don't end a record
+pub struct ChunkReader<R> {
+    input: R,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+    line: u64,
+    byte: u64,
+    eof: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub fn new(input: R, chunk_size: usize) -> Self {
+        ChunkReader {
+            input,
+            chunk_size: chunk_size.max(1),
+            buffer: Vec::new(),
+            line: 1,
+            byte: 0,
+            eof: false,
+        }
+    }
+
+    /// Header record of the input, has to be read before the chunks
+    pub fn headers(&mut self) -> Result<StringRecord, csv::Error> {
+        let data = match self.take(false)? {
+            Some(chunk) => chunk.data,
+            None => Vec::new(),
+        };
+
+        let mut reader = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(data.as_slice());
+
+        Ok(reader.headers()?.clone())
+    }
+
+    /// Next chunk of at most the chunk size unless a single record is longer, `None` at the end of the input
+    pub fn next_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        self.take(true)
+    }
+
+    // Takes everything up to the first (or the last) record boundary, reading more while there is none
+    fn take(&mut self, whole_chunk: bool) -> io::Result<Option<Chunk>> {
+        let mut wanted = self.chunk_size;
+
+        loop {
+            self.fill(wanted)?;
+
+            // The last record of the input doesn't need a newline
+            let end = if self.eof && whole_chunk {
+                Some(self.buffer.len())
+            } else {
+                record_end(&self.buffer, whole_chunk).or(self.eof.then_some(self.buffer.len()))
+            };
+
+            if let Some(end) = end {
+                if end == 0 {
+                    return Ok(None);
+                }
+
+                let rest = self.buffer.split_off(end);
+                let data = std::mem::replace(&mut self.buffer, rest);
+                let chunk = Chunk { line: self.line, byte: self.byte, data };
+
+                self.line += chunk.data.iter().filter(|byte| **byte == b'\n').count() as u64;
+                self.byte += end as u64;
+                return Ok(Some(chunk));
+            }
+
+            wanted = self.buffer.len() + self.chunk_size;
+        }
+    }
+
+    fn fill(&mut self, wanted: usize) -> io::Result<()> {
+        if self.buffer.len() < wanted && !self.eof {
+            let missing = (wanted - self.buffer.len()) as u64;
+            let read = (&mut self.input).take(missing).read_to_end(&mut self.buffer)?;
+            self.eof = (read as u64) < missing;
+        }
+
+        Ok(())
+    }
+}
+
+// Where the csv reader is within a record, a quote only opens a quoted field at the start of the field
+#[derive(Clone, Copy, PartialEq)]
+enum FieldState {
+    Start,
+    Unquoted,
+    Quoted,
+    // Quote inside a quoted field, either the end of the quotes or the first half of an escaped quote
+    QuoteInQuoted,
+}
+
+// Index after the first or the last record terminator (`\n` or `\r`) outside of quotes, the buffer starts at
+// a record boundary. Terminators of empty lines are skipped like by the csv reader, which reads them together with
+// the next record, so the chunks start where its records do and get the same lines.
+fn record_end(buffer: &[u8], last: bool) -> Option<usize> {
+    let mut state = FieldState::Start;
+    let mut in_record = false;
+    let mut end = None;
+
+    for (index, byte) in buffer.iter().enumerate() {
+        state = match (state, byte) {
+            (FieldState::Quoted, b'"') => FieldState::QuoteInQuoted,
+            (FieldState::Quoted, _) => FieldState::Quoted,
+            (FieldState::Start, b'"') | (FieldState::QuoteInQuoted, b'"') => FieldState::Quoted,
+            (_, b',') => FieldState::Start,
+            (_, b'\n' | b'\r') => {
+                if in_record {
+                    end = Some(index + 1);
+                    if !last {
+                        break;
+                    }
+                }
+                in_record = false;
+                state = FieldState::Start;
+                continue;
+            }
+            _ => FieldState::Unquoted,
+        };
+        in_record = true;
+    }
+
+    end
+}
+
+/// Parses the rows of a chunk the same way as the sequential reader, with the same lines in the errors
+pub fn parse_chunk(chunk: Chunk, parser: &RecordParser) -> Result<Vec<ParsedRow>, String> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::None)
+        .flexible(true)
+        .from_reader(Cursor::new(chunk.data));
+
+    // The header is the record 0, so without multi-line records the record index is the line minus one
+    let mut position = Position::new();
+    position.set_byte(chunk.byte).set_line(chunk.line).set_record(chunk.line - 1);
+    reader.seek_raw(SeekFrom::Start(0), position).map_err(|e| e.to_string())?;
+
+    let mut rows = Vec::new();
+    let mut record = ByteRecord::new();
+    loop {
+        match reader.read_byte_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) if err.is_io_error() => return Err(err.to_string()),
+            // Reported like the rows which fail to parse, without stopping the chunk
+            Err(err) => {
+                let line = err.position().map(|position| position.line()).unwrap_or_default();
+                rows.push((line, Err(err.to_string())));
+                continue;
+            }
+        }
+
+        let line = record.position().map(|position| position.line()).unwrap_or_default();
+        rows.push((line, parser.parse(&record).and_then(|row| row)));
+    }
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::TransactionType;
+
+    fn chunks(input: &str, chunk_size: usize) -> (StringRecord, Vec<Chunk>) {
+        let mut reader = ChunkReader::new(input.as_bytes(), chunk_size);
+        let headers = reader.headers().unwrap();
+
+        let mut chunks = Vec::new();
+        while let Some(chunk) = reader.next_chunk().unwrap() {
+            chunks.push(chunk);
+        }
+
+        (headers, chunks)
+    }
+
+    fn text(chunk: &Chunk) -> &str {
+        std::str::from_utf8(&chunk.data).unwrap()
+    }
+
+    #[test]
+    fn test_chunks_end_at_record_boundaries() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\nwithdrawal,1,3,0.5\n";
+        let (headers, chunks) = chunks(input, 40);
+
+        assert_eq!(headers.iter().collect::<Vec<_>>(), ["type", "client", "tx", "amount"]);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(text(&chunks[0]), "deposit,1,1,1.0\ndeposit,2,2,2.0\n");
+        assert_eq!((chunks[0].line, chunks[0].byte), (2, 22));
+        assert_eq!(text(&chunks[1]), "withdrawal,1,3,0.5\n");
+        assert_eq!((chunks[1].line, chunks[1].byte), (4, 54));
+    }
+
+    #[test]
+    fn test_chunk_grows_for_long_records_and_keeps_quoted_newlines() {
+        let input = "type,client,tx,amount\n\"deposit\",1,1,\"1\n.0\"\ndeposit,2,2,2.0";
+        let (_, chunks) = chunks(input, 4);
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(text(&chunks[0]), "\"deposit\",1,1,\"1\n.0\"\n");
+        // The quoted newline counts as a line like in the csv reader
+        assert_eq!(chunks[1].line, 4);
+        assert_eq!(text(&chunks[1]), "deposit,2,2,2.0");
+    }
+
+    #[test]
+    fn test_header_only_and_empty_input() {
+        let (headers, chunks) = self::chunks("type,client,tx,amount", 1024);
+        assert_eq!(headers.len(), 4);
+        assert!(chunks.is_empty());
+
+        let (headers, chunks) = self::chunks("", 1024);
+        assert!(headers.is_empty());
+        assert!(chunks.is_empty());
+    }
+
+    #[test]
+    fn test_parse_chunk_reports_input_lines() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nbogus,1,2,1.0\nwithdrawal, 1, 3, 0.5\n";
+        let (headers, chunks) = chunks(input, 30);
+        let parser = RecordParser::new(&headers);
+
+        let rows: Vec<ParsedRow> = chunks.into_iter()
+            .flat_map(|chunk| parse_chunk(chunk, &parser).unwrap())
+            .collect();
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].0, 2);
+        assert_eq!(rows[0].1.as_ref().unwrap().transaction_type, TransactionType::Deposit);
+        assert_eq!(rows[1].0, 3);
+        assert!(rows[1].1.as_ref().unwrap_err().contains("line: 3"));
+        assert_eq!(rows[2].0, 4);
+        assert_eq!(rows[2].1.as_ref().unwrap().tx, 3);
+    }
+
+    // Rows as `Intake::read_csv` reads them, the errors are left out as they name the record index, which
+    // the chunks only know for single-line records
+    fn sequential_rows(input: &str) -> Vec<(u64, Option<TransactionEntity>)> {
+        let mut reader = ReaderBuilder::new().has_headers(true).trim(csv::Trim::Headers).flexible(true).from_reader(input.as_bytes());
+        let parser = RecordParser::new(&reader.headers().unwrap().clone());
+
+        let mut rows = Vec::new();
+        let mut record = ByteRecord::new();
+        while reader.read_byte_record(&mut record).unwrap() {
+            let line = record.position().map(|position| position.line()).unwrap_or_default();
+            rows.push((line, parser.parse(&record).and_then(|row| row).ok()));
+        }
+
+        rows
+    }
+
+    #[test]
+    fn test_chunks_give_rows_of_sequential_reader() {
+        let inputs = [
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n",
+            // A quote inside an unquoted field is a plain character, the newline after the quoted field ends the record
+            "type,client,tx,amount\ndeposit,1,1,a\"b,\"x\ny\"\ndeposit,1,2,2.0\n",
+            "type,client,tx,amount\n\"deposit\",1,1,\"1\n.0\"\n\"dep\"\"osit\",1,2,2.0\ndeposit,1,3,3.0",
+            "type,client,tx,amount\ndeposit,1,1,1.0\n\"deposit\n\",1,2,2.0\n\n\ndeposit,1,3,3.0\n",
+            "type,client,tx,amount\rdeposit,1,1,1.0\rdeposit,1,2,2.0\r",
+            "type,client,tx,amount\r\ndeposit,1,1,1.0\r\n\r\ndeposit,1,2,\"2.0\r\n\"\r\nwithdrawal,1,3,1.5",
+        ];
+
+        for input in inputs {
+            let expected = sequential_rows(input);
+            assert!(expected.iter().any(|(_, row)| row.is_some()));
+
+            for chunk_size in 1..=input.len() {
+                let (headers, chunks) = chunks(input, chunk_size);
+                let parser = RecordParser::new(&headers);
+                let rows: Vec<_> = chunks.into_iter()
+                    .flat_map(|chunk| parse_chunk(chunk, &parser).unwrap())
+                    .map(|(line, row)| (line, row.ok()))
+                    .collect();
+
+                assert_eq!(rows, expected, "{:?} in chunks of {}", input, chunk_size);
+            }
+        }
+    }
+}
@@ -1,71 +1,431 @@
 use std::{collections::HashMap, sync::Arc};
 
-use tokio::sync::mpsc;
+use rust_decimal::Decimal;
+use tokio::sync::{mpsc, oneshot};
 use tokio::sync::RwLock;
-use crate::transaction::TransactionEntity;
-use crate::account::{Account, AccountEntity, AccountWorker, AccountWorkerMessage};
+use crate::config::{EngineConfig, EngineMode};
+use crate::currency::Currency;
+use crate::history::{write_statement, HistoryEntry, StatementFormat};
+use crate::ledger::{merge_balances, verify_totals, AccountTotals, LedgerAccount};
+use crate::transaction::{TransactionEntity, TransactionOutcome, TransactionType};
+use crate::account::{Account, AccountEntity, AccountQuery, AccountWorker, AccountWorkerMessage, TransferLeg};
 use std::error::Error;
+use std::io::Write;
+use csv::WriterBuilder;
 
-const WORKER_CHANNEL_SIZE: usize = 100;
+/// Capacity of the worker channels unless `EngineConfig::channel_size` is set
+pub const WORKER_CHANNEL_SIZE: usize = 100;
+
+/// Transaction submitted to the workers, its outcome is available once the worker has processed it
+pub struct PendingOutcome {
+    entity: TransactionEntity,
+    reply: oneshot::Receiver<Result<(), String>>,
+}
+
+impl PendingOutcome {
+    pub async fn outcome(self) -> TransactionOutcome {
+        let result = match self.reply.await {
+            Ok(result) => result,
+            Err(_) => Err("Worker stopped before processing the transaction".to_string()),
+        };
+
+        TransactionOutcome::new(&self.entity, result)
+    }
+}
 
 pub struct PaymentEngine {
+    // Keyed by worker, the client itself unless the engine is sharded
     account_senders: HashMap<u16, mpsc::Sender<AccountWorkerMessage>>,
     accounts: HashMap<u16, Arc<RwLock<Account>>>,
     spawned_workers: HashMap<u16, tokio::task::JoinHandle<()>>,
+    // Processes the messages in place in the synchronous mode
+    inline_worker: AccountWorker,
+    config: Arc<EngineConfig>,
+    // Sequence number of the next transaction entering the engine
+    next_seq: u64,
+    // Set by `shutdown`, no more transactions are accepted after it
+    stopped: bool,
+}
+
+impl Default for PaymentEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PaymentEngine {
     pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
         PaymentEngine {
             account_senders: HashMap::new(),
             accounts: HashMap::new(),
             spawned_workers: HashMap::new(),
+            inline_worker: AccountWorker::inline(),
+            config: Arc::new(config),
+            next_seq: 1,
+            stopped: false,
         }
     }
 
-    async fn add_account_if_not_exists(&mut self, client_id: u16) -> mpsc::Sender<AccountWorkerMessage> {
-        if let Some(sender) = self.account_senders.get(&client_id) {
-            return sender.clone();
+    fn worker_id(&self, client_id: u16) -> u16 {
+        match self.config.mode {
+            EngineMode::ActorPerClient => client_id,
+            EngineMode::Sharded(shards) => (client_id as usize % shards.max(1)) as u16,
+            EngineMode::Synchronous => 0,
+        }
+    }
+
+    async fn add_account_if_not_exists(&mut self, client_id: u16) -> Result<(), Box<dyn Error>> {
+        if self.accounts.contains_key(&client_id) {
+            return Ok(());
+        }
+
+        let account_arc = Arc::new(RwLock::new(Account::with_config(client_id, self.config.clone())));
+        self.accounts.insert(client_id, account_arc.clone());
+
+        if self.config.mode == EngineMode::Synchronous {
+            self.inline_worker.register(client_id, account_arc);
+            return Ok(());
+        }
+
+        // A new worker picks up its accounts when it is spawned, a running one gets them as a message
+        let worker_id = self.worker_id(client_id);
+        let sender = match self.account_senders.get(&worker_id) {
+            Some(sender) => sender.clone(),
+            None => {
+                self.spawn_worker(worker_id);
+                return Ok(());
+            }
+        };
+
+        if sender.send(AccountWorkerMessage::Register(client_id, account_arc)).await.is_err() {
+            self.restart_worker(worker_id).await;
+        }
+
+        Ok(())
+    }
+
+    fn spawn_worker(&mut self, worker_id: u16) -> mpsc::Sender<AccountWorkerMessage> {
+        // A channel needs room for at least one message
+        let (tx, rx) = mpsc::channel(self.config.channel_size.unwrap_or(WORKER_CHANNEL_SIZE).max(1));
+        let mut worker = AccountWorker::new(rx);
+        for (client_id, account) in self.accounts.iter() {
+            if self.worker_id(*client_id) == worker_id {
+                worker.register(*client_id, account.clone());
+            }
         }
 
-        let (tx, rx) = mpsc::channel(WORKER_CHANNEL_SIZE);
-        let account_arc = Arc::new(RwLock::new(Account::new(client_id)));
-        let worker = AccountWorker::new( rx, account_arc.clone());
-        
         let handler = tokio::spawn(async move {
             worker.run().await;
         });
 
-        self.spawned_workers.insert(client_id, handler);
-        self.account_senders.insert(client_id, tx.clone());
-        self.accounts.insert(client_id, account_arc);
+        self.spawned_workers.insert(worker_id, handler);
+        self.account_senders.insert(worker_id, tx.clone());
         tx
     }
 
+    // Panics while processing are handled by the worker itself. If the worker task is gone anyway, a new one is
+    // started for the account after recovering it and the message is sent to it, so the other clients are not affected.
+    async fn send(&mut self, client_id: u16, message: AccountWorkerMessage) -> Result<(), Box<dyn Error>> {
+        self.add_account_if_not_exists(client_id).await?;
+
+        if self.config.mode == EngineMode::Synchronous {
+            self.inline_worker.handle(message).await;
+            return Ok(());
+        }
+
+        let worker_id = self.worker_id(client_id);
+        let message = match self.account_senders[&worker_id].send(message).await {
+            Ok(()) => return Ok(()),
+            Err(mpsc::error::SendError(message)) => message,
+        };
+
+        let sender = self.restart_worker(worker_id).await;
+        sender.send(message).await?;
+        Ok(())
+    }
+
+    // With sharding the state of every account of the dead worker is unknown, so all of them are recovered
+    async fn restart_worker(&mut self, worker_id: u16) -> mpsc::Sender<AccountWorkerMessage> {
+        if let Some(handle) = self.spawned_workers.remove(&worker_id) {
+            if let Err(e) = handle.await {
+                eprintln!("Worker {} died: {}", worker_id, e);
+            }
+        }
+
+        for (client_id, account) in self.accounts.iter() {
+            if self.worker_id(*client_id) == worker_id {
+                account.write().await.recover();
+            }
+        }
+        eprintln!("Worker {} restarted", worker_id);
+
+        self.spawn_worker(worker_id)
+    }
+
+    /// Clients whose accounts reject transactions after their worker panicked
+    pub async fn faulted_clients(&self) -> Vec<u16> {
+        let mut clients = Vec::new();
+        for (client_id, account) in self.accounts.iter() {
+            if account.read().await.is_faulted() {
+                clients.push(*client_id);
+            }
+        }
+
+        clients.sort();
+        clients
+    }
+
     pub async fn get_account_entities(&self, order: bool) -> Vec<AccountEntity> {
         let mut account_entities = Vec::new();
 
         for (_, account) in self.accounts.iter() {
             let account_guard = account.read().await;
-            let account_entity = AccountEntity::from(&*account_guard);
-            account_entities.push(account_entity);
+            account_entities.extend(account_guard.entities());
         }
 
         if order {
-            account_entities.sort_by_key(|a| a.client);
+            account_entities.sort_by(|a, b| (a.client, &a.currency).cmp(&(b.client, &b.currency)));
         }
 
         account_entities
     }
 
-    pub async fn process_transaction(&mut self, transaction_entity: TransactionEntity) -> Result<(), Box<dyn Error>> {
-        let account_sender = self.add_account_if_not_exists(transaction_entity.client).await;
+    /// Balances of all the clients ordered by client and currency, works while the engine is running
+    pub async fn list_accounts(&self) -> Result<Vec<AccountEntity>, Box<dyn Error>> {
+        let mut clients: Vec<u16> = self.accounts.keys().copied().collect();
+        clients.sort();
+
+        let mut account_entities = Vec::new();
+        for client_id in clients {
+            account_entities.extend(self.get_account(client_id).await?);
+        }
+
+        Ok(account_entities)
+    }
+
+    /// Writes the balances of all the clients as CSV with the currency column, returns the number of rows
+    pub async fn write_snapshot<W: Write>(&self, output: W) -> Result<usize, Box<dyn Error>> {
+        let accounts = self.list_accounts().await?;
+
+        let mut writer = WriterBuilder::new()
+            .has_headers(true)
+            .from_writer(output);
+
+        for account in accounts.iter() {
+            writer.serialize(account)?;
+        }
+
+        writer.flush()?;
+        Ok(accounts.len())
+    }
+
+    /// Current balances of the client while the engine is running, every transaction processed before is applied
+    pub async fn get_account(&self, client_id: u16) -> Result<Vec<AccountEntity>, Box<dyn Error>> {
+        self.query(client_id, |account| account.entities()).await
+    }
+
+    /// Balance of the client right after the transactions up to the sequence number `seq` were applied, requires
+    /// history to be enabled in the config
+    pub async fn get_account_after(&self, client_id: u16, seq: u64) -> Result<AccountEntity, Box<dyn Error>> {
+        let entity = self.query(client_id, move |account| account.entity_after(seq).map_err(|e| e.to_string())).await?;
+        Ok(entity?)
+    }
+
+    // Queries go through the worker, so they see the account after all the previously sent transactions
+    // without taking the lock from the engine side
+    async fn query<T, F>(&self, client_id: u16, query: F) -> Result<T, Box<dyn Error>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Account) -> T + Send + 'static,
+    {
+        let account = match self.accounts.get(&client_id) {
+            Some(account) => account,
+            None => return Err(format!("Account {} not found", client_id).into()),
+        };
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let query: AccountQuery = Box::new(move |account| {
+            let _ = reply_tx.send(query(account));
+        });
+
+        // Without a worker (the synchronous mode or after shutdown) the account is up to date and read directly
+        match self.account_senders.get(&self.worker_id(client_id)) {
+            Some(sender) => {
+                if let Err(mpsc::error::SendError(AccountWorkerMessage::Query(_, query))) = sender.send(AccountWorkerMessage::Query(client_id, query)).await {
+                    query(&*account.read().await);
+                }
+            }
+            None => query(&*account.read().await),
+        }
+
+        Ok(reply_rx.await?)
+    }
+
+    /// Writes the history of the client account, requires history to be enabled in the config
+    pub async fn export_statement<W: Write>(&self, client_id: u16, format: StatementFormat, output: W) -> Result<(), Box<dyn Error>> {
+        let account = match self.accounts.get(&client_id) {
+            Some(account) => account,
+            None => return Err(format!("Account {} not found", client_id).into()),
+        };
+
+        let account_guard = account.read().await;
+        match account_guard.history() {
+            Some(history) => write_statement(history, format, output),
+            None => Err("History is not enabled".into()),
+        }
+    }
+
+    /// History of all the accounts in the order the engine applied it, requires history to be enabled in the config
+    pub async fn history_entries(&self) -> Result<Vec<HistoryEntry>, Box<dyn Error>> {
+        let mut entries = Vec::new();
+        for account in self.accounts.values() {
+            match account.read().await.history() {
+                Some(history) => entries.extend_from_slice(history),
+                None => return Err("History is not enabled".into()),
+            }
+        }
+
+        // Both accounts of a transfer have an entry with its sequence number, they are ordered by the leg. The sort
+        // is stable, so the entries of one account keep their order.
+        entries.sort_by_key(|entry| (entry.seq, entry.leg));
+        Ok(entries)
+    }
+
+    pub async fn process_transaction(&mut self, mut transaction_entity: TransactionEntity) -> Result<(), Box<dyn Error>> {
+        self.ensure_running()?;
+        self.assign_sequence(&mut transaction_entity);
+
+        if transaction_entity.transaction_type == TransactionType::Transfer {
+            return self.process_transfer(transaction_entity).await;
+        }
+
+        self.send(transaction_entity.client, AccountWorkerMessage::Transaction(transaction_entity, None)).await
+    }
+
+    /// Like `process_transaction`, but the outcome of the transaction is reported back instead of being logged.
+    /// Only the sending is awaited, so transactions of other clients can be submitted while this one is processed.
+    pub async fn submit_transaction(&mut self, mut transaction_entity: TransactionEntity) -> Result<PendingOutcome, Box<dyn Error>> {
+        self.ensure_running()?;
+        self.assign_sequence(&mut transaction_entity);
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        if transaction_entity.transaction_type == TransactionType::Transfer {
+            let result = self.transfer(&transaction_entity).await?;
+            let _ = reply_tx.send(result);
+        } else {
+            self.send(transaction_entity.client, AccountWorkerMessage::Transaction(transaction_entity.clone(), Some(reply_tx))).await?;
+        }
+
+        Ok(PendingOutcome { entity: transaction_entity, reply: reply_rx })
+    }
+
+    // Numbers the transactions in the order they enter the engine, every worker receives them in that order
+    fn assign_sequence(&mut self, transaction_entity: &mut TransactionEntity) {
+        transaction_entity.seq = Some(self.next_seq);
+        self.next_seq += 1;
+    }
+
+    /// Fees charged from all the accounts per currency, i.e. the balance of the fee income house account
+    pub async fn fee_income(&self) -> HashMap<Currency, Decimal> {
+        let mut income: HashMap<Currency, Decimal> = HashMap::new();
+
+        for account in self.accounts.values() {
+            let account_guard = account.read().await;
+            for (currency, balance) in account_guard.balances() {
+                *income.entry(currency.clone()).or_default() += balance.fees;
+            }
+        }
+
+        income
+    }
+
+    /// Checks that every client ledger matches its account and the merged ledger matches the totals of all accounts
+    pub async fn verify_ledger(&self) -> Result<HashMap<(LedgerAccount, Currency), Decimal>, Box<dyn Error>> {
+        let mut guards = Vec::with_capacity(self.accounts.len());
+        for account in self.accounts.values() {
+            guards.push(account.read().await);
+        }
+
+        let mut totals: HashMap<Currency, AccountTotals> = HashMap::new();
+        for account in guards.iter() {
+            account.reconcile_ledger()?;
+            for (currency, balance) in account.balances() {
+                let currency_totals = totals.entry(currency.clone()).or_default();
+                currency_totals.total += balance.total;
+                currency_totals.fees += balance.fees;
+            }
+        }
+
+        let balances = merge_balances(guards.iter().filter_map(|account| account.ledger()));
+        verify_totals(&balances, &totals)?;
+        Ok(balances)
+    }
+
+    async fn process_transfer(&mut self, transaction_entity: TransactionEntity) -> Result<(), Box<dyn Error>> {
+        if let Err(e) = self.transfer(&transaction_entity).await? {
+            eprintln!("Error processing transfer #{}: {}", transaction_entity.seq.unwrap_or_default(), e);
+        }
 
-        account_sender.send(AccountWorkerMessage::Transaction(transaction_entity)).await?;
         Ok(())
     }
 
-    pub async fn shutdown(&mut self) {
+    // The source is debited first and the destination is credited only after that, a failed credit
+    // returns the funds to the source. Waiting for every step keeps the order of the messages of both
+    // clients, nothing else is sent to them while the transfer is in progress.
+    async fn transfer(&mut self, transaction_entity: &TransactionEntity) -> Result<Result<(), String>, Box<dyn Error>> {
+        let to_client = match transaction_entity.to_client {
+            Some(to_client) if to_client != transaction_entity.client => to_client,
+            _ => return Ok(Err("Destination client is invalid".to_string())),
+        };
+
+        let (debit_tx, debit_rx) = oneshot::channel();
+        self.send(transaction_entity.client, AccountWorkerMessage::Transfer(transaction_entity.clone(), TransferLeg::Debit, Some(debit_tx))).await?;
+
+        if let Err(e) = Self::leg_result(debit_rx).await {
+            return Ok(Err(e));
+        }
+
+        let (credit_tx, credit_rx) = oneshot::channel();
+        self.send(to_client, AccountWorkerMessage::Transfer(transaction_entity.clone(), TransferLeg::Credit, Some(credit_tx))).await?;
+
+        if let Err(e) = Self::leg_result(credit_rx).await {
+            self.send(transaction_entity.client, AccountWorkerMessage::Transfer(transaction_entity.clone(), TransferLeg::Rollback, None)).await?;
+            return Ok(Err(format!("{}, the funds are returned to the source", e)));
+        }
+
+        Ok(Ok(()))
+    }
+
+    async fn leg_result(reply: oneshot::Receiver<Result<(), String>>) -> Result<(), String> {
+        match reply.await {
+            Ok(result) => result,
+            Err(_) => Err("Worker stopped before processing the transfer".to_string()),
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    fn ensure_running(&self) -> Result<(), Box<dyn Error>> {
+        if self.stopped {
+            return Err("Engine is shut down".into());
+        }
+
+        Ok(())
+    }
+
+    /// Stops the workers after they have processed everything already sent to them
+    pub async fn shutdown(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.stopped {
+            return Ok(());
+        }
+        self.stopped = true;
+
         // First send shutdown message to all workers
         for (_, sender) in self.account_senders.iter_mut() {
             if let Err(e) = sender.send(AccountWorkerMessage::Shutdown).await {
@@ -74,10 +434,138 @@ impl PaymentEngine {
         }
 
         // Then wait for all workers to complete
-        for (client_id, handle) in self.spawned_workers.drain() {
+        for (worker_id, handle) in self.spawned_workers.drain() {
             if let Err(e) = handle.await {
-                eprintln!("Worker for client {} failed to shutdown: {}", client_id, e);
+                eprintln!("Worker {} failed to shutdown: {}", worker_id, e);
             }
         }
+
+        if self.config.double_entry {
+            self.verify_ledger().await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SupervisionPolicy;
+    use crate::transaction::OutcomeStatus;
+    use rust_decimal_macros::dec;
+
+    fn deposit(client: u16, tx: u32, amount: Decimal) -> TransactionEntity {
+        TransactionEntity::new(TransactionType::Deposit, client, tx, Some(amount))
+    }
+
+    // Kills the worker task as if it died outside of the isolated processing
+    async fn kill_worker(engine: &mut PaymentEngine, worker_id: u16) {
+        let handle = engine.spawned_workers.get(&worker_id).unwrap();
+        handle.abort();
+        while !handle.is_finished() {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    async fn run_with_dead_worker(policy: SupervisionPolicy) -> (PaymentEngine, TransactionOutcome) {
+        let mut engine = PaymentEngine::with_config(EngineConfig { supervision: policy, ..Default::default() });
+
+        engine.submit_transaction(deposit(1, 1, dec!(10.0))).await.unwrap().outcome().await;
+        engine.submit_transaction(deposit(2, 2, dec!(7.0))).await.unwrap().outcome().await;
+        kill_worker(&mut engine, 1).await;
+
+        let outcome = engine.submit_transaction(deposit(1, 3, dec!(5.0))).await.unwrap().outcome().await;
+        engine.process_transaction(deposit(2, 4, dec!(1.0))).await.unwrap();
+        engine.shutdown().await.unwrap();
+
+        (engine, outcome)
+    }
+
+    #[tokio::test]
+    async fn test_dead_worker_faults_account() {
+        let (engine, outcome) = run_with_dead_worker(SupervisionPolicy::Fault).await;
+
+        assert_eq!(outcome.status, OutcomeStatus::Rejected);
+        assert_eq!(outcome.error.as_deref(), Some("Account is faulted"));
+        assert_eq!(engine.faulted_clients().await, vec![1]);
+
+        let accounts = engine.get_account_entities(true).await;
+        assert_eq!(accounts[0].total, dec!(10.0));
+        assert_eq!(accounts[1].total, dec!(8.0));
+    }
+
+    #[tokio::test]
+    async fn test_dead_worker_rebuilds_account() {
+        let (engine, outcome) = run_with_dead_worker(SupervisionPolicy::Rebuild).await;
+
+        assert_eq!(outcome.status, OutcomeStatus::Applied);
+        assert!(engine.faulted_clients().await.is_empty());
+
+        let accounts = engine.get_account_entities(true).await;
+        assert_eq!(accounts[0].total, dec!(15.0));
+        assert_eq!(accounts[1].total, dec!(8.0));
+    }
+
+    #[tokio::test]
+    async fn test_dead_shard_recovers_all_its_accounts() {
+        let config = EngineConfig { mode: EngineMode::Sharded(2), supervision: SupervisionPolicy::Rebuild, ..Default::default() };
+        let mut engine = PaymentEngine::with_config(config);
+
+        for (client, tx) in [(1, 1), (2, 2), (3, 3)] {
+            engine.submit_transaction(deposit(client, tx, dec!(4.0))).await.unwrap().outcome().await;
+        }
+        // Clients 1 and 3 share the worker 1
+        kill_worker(&mut engine, 1).await;
+
+        let outcome = engine.submit_transaction(deposit(3, 4, dec!(1.0))).await.unwrap().outcome().await;
+        engine.shutdown().await.unwrap();
+
+        assert_eq!(outcome.status, OutcomeStatus::Applied);
+        let totals: Vec<Decimal> = engine.get_account_entities(true).await.iter().map(|account| account.total).collect();
+        assert_eq!(totals, vec![dec!(4.0), dec!(4.0), dec!(5.0)]);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_fails_for_wrong_ledger_entry() {
+        let mut engine = PaymentEngine::with_config(EngineConfig { double_entry: true, ..Default::default() });
+        engine.submit_transaction(deposit(1, 1, dec!(10.0))).await.unwrap().outcome().await;
+
+        // Funds leaving the settlement into transit without a transfer, the client ledger still matches the account
+        let account = engine.accounts[&1].clone();
+        account.write().await.ledger_mut().unwrap()
+            .post(2, "USD", LedgerAccount::Settlement, LedgerAccount::TransfersInTransit, dec!(5.0));
+
+        let err = engine.shutdown().await.unwrap_err();
+        assert_eq!(err.to_string(), "Ledger has 5.0 USD of transfers in transit");
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_fails_for_unbalanced_ledger_entry() {
+        let mut engine = PaymentEngine::with_config(EngineConfig { double_entry: true, ..Default::default() });
+        engine.submit_transaction(deposit(1, 1, dec!(10.0))).await.unwrap().outcome().await;
+
+        // Only the settlement side, the client ledger still matches the account
+        let account = engine.accounts[&1].clone();
+        account.write().await.ledger_mut().unwrap().post_unbalanced("USD", LedgerAccount::Settlement, dec!(-3.0));
+
+        let err = engine.shutdown().await.unwrap_err();
+        assert_eq!(err.to_string(), "Ledger balances of USD sum to -3.0 instead of zero");
+    }
+
+    #[tokio::test]
+    async fn test_history_entries_keep_transfer_legs_in_order() {
+        let mut engine = PaymentEngine::with_config(EngineConfig { history: true, ..Default::default() });
+        engine.process_transaction(deposit(2, 1, dec!(10.0))).await.unwrap();
+        engine.process_transaction(TransactionEntity { transaction_type: TransactionType::Transfer, to_client: Some(1), ..deposit(2, 2, dec!(4.0)) }).await.unwrap();
+        engine.process_transaction(deposit(1, 3, dec!(1.0))).await.unwrap();
+        engine.shutdown().await.unwrap();
+
+        let entries = engine.history_entries().await.unwrap();
+        let order: Vec<(Option<u64>, u16)> = entries.iter().map(|entry| (entry.seq, entry.client)).collect();
+        // The debit of client 2 comes before the credit of client 1
+        assert_eq!(order, [(Some(1), 2), (Some(2), 2), (Some(2), 1), (Some(3), 1)]);
+        let legs: Vec<Option<TransferLeg>> = entries.iter().map(|entry| entry.leg).collect();
+        assert_eq!(legs, [None, Some(TransferLeg::Debit), Some(TransferLeg::Credit), None]);
     }
 }
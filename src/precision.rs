@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::currency::Currency;
+use crate::decimal::DECIMAL_PRECISION;
+
+/// What to do with input amounts which have more fractional digits than the currency allows
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PrecisionPolicy {
+    #[default]
+    Round,
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Precision {
+    pub scale: u32,
+    pub rounding: RoundingStrategy,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Precision {
+            scale: DECIMAL_PRECISION,
+            rounding: RoundingStrategy::ToZero,
+        }
+    }
+}
+
+impl Precision {
+    pub fn new(scale: u32, rounding: RoundingStrategy) -> Self {
+        Precision { scale, rounding }
+    }
+
+    pub fn round(&self, amount: Decimal) -> Decimal {
+        amount.round_dp_with_strategy(self.scale, self.rounding)
+    }
+
+    /// Trailing zeros do not count, `1.50` fits into one fractional digit
+    pub fn fits(&self, amount: Decimal) -> bool {
+        amount.normalize().scale() <= self.scale
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PrecisionConfig {
+    pub default: Precision,
+    pub currencies: HashMap<Currency, Precision>,
+    pub policy: PrecisionPolicy,
+}
+
+impl PrecisionConfig {
+    pub fn with_currency(mut self, currency: &str, precision: Precision) -> Self {
+        self.currencies.insert(currency.to_string(), precision);
+        self
+    }
+
+    pub fn for_currency(&self, currency: &str) -> Precision {
+        self.currencies.get(currency).copied().unwrap_or(self.default)
+    }
+
+    /// Largest scale of the default and the currencies, every amount fits into it
+    pub fn max_scale(&self) -> u32 {
+        self.currencies.values().map(|precision| precision.scale).fold(self.default.scale, u32::max)
+    }
+
+    /// Brings an input amount to the precision of the currency according to the policy
+    pub fn apply(&self, amount: Decimal, currency: &str) -> Result<Decimal, Box<dyn Error>> {
+        let precision = self.for_currency(currency);
+
+        if precision.fits(amount) {
+            return Ok(amount);
+        }
+
+        match self.policy {
+            PrecisionPolicy::Round => Ok(precision.round(amount)),
+            PrecisionPolicy::Reject => Err(format!(
+                "Amount {} has more than {} fractional digits allowed for {}",
+                amount, precision.scale, currency
+            ).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn config(policy: PrecisionPolicy) -> PrecisionConfig {
+        PrecisionConfig { policy, ..Default::default() }
+            .with_currency("JPY", Precision::new(0, RoundingStrategy::ToZero))
+            .with_currency("BTC", Precision::new(8, RoundingStrategy::MidpointNearestEven))
+    }
+
+    #[test]
+    fn test_default_precision_truncates() {
+        assert_eq!(config(PrecisionPolicy::Round).apply(dec!(1.23456789), "USD").unwrap(), dec!(1.2345));
+        assert_eq!(config(PrecisionPolicy::Round).apply(dec!(-1.23456789), "USD").unwrap(), dec!(-1.2345));
+    }
+
+    #[test]
+    fn test_precision_per_currency() {
+        let config = config(PrecisionPolicy::Round);
+
+        assert_eq!(config.apply(dec!(100.99), "JPY").unwrap(), dec!(100));
+        assert_eq!(config.apply(dec!(0.123456785), "BTC").unwrap(), dec!(0.12345678));
+        assert_eq!(config.apply(dec!(0.123456775), "BTC").unwrap(), dec!(0.12345678));
+        assert_eq!(config.apply(dec!(0.12345678), "BTC").unwrap(), dec!(0.12345678));
+    }
+
+    #[test]
+    fn test_reject_policy() {
+        let config = config(PrecisionPolicy::Reject);
+
+        assert!(config.apply(dec!(100.5), "JPY").is_err());
+        assert!(config.apply(dec!(1.23456), "USD").is_err());
+        assert_eq!(config.apply(dec!(100.000), "JPY").unwrap(), dec!(100.000));
+        assert_eq!(config.apply(dec!(1.2345), "USD").unwrap(), dec!(1.2345));
+    }
+
+    #[test]
+    fn test_max_scale() {
+        assert_eq!(PrecisionConfig::default().max_scale(), DECIMAL_PRECISION);
+        assert_eq!(config(PrecisionPolicy::Round).max_scale(), 8);
+    }
+}
@@ -0,0 +1,122 @@
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+
+use crate::account::AccountEntity;
+use crate::compression;
+use crate::shutdown::{self, requested, ShutdownReceiver};
+use crate::payment_engine::PaymentEngine;
+use crate::transaction::{TransactionEntity, TransactionOutcome};
+
+type ApiError = (StatusCode, String);
+
+/// Engine shared by the HTTP handlers, the lock is held only while a transaction is handed to the workers
+#[derive(Clone)]
+pub struct ServerState {
+    engine: Arc<Mutex<PaymentEngine>>,
+    snapshot: Option<PathBuf>,
+}
+
+impl ServerState {
+    pub fn new(engine: PaymentEngine, snapshot: Option<PathBuf>) -> Self {
+        ServerState {
+            engine: Arc::new(Mutex::new(engine)),
+            snapshot,
+        }
+    }
+
+    pub fn engine(&self) -> Arc<Mutex<PaymentEngine>> {
+        self.engine.clone()
+    }
+
+    /// Drains the workers and writes the balances of all the clients to the output and to the snapshot if configured
+    pub async fn shutdown<W: Write>(&self, output: W) -> Result<(), Box<dyn Error>> {
+        let mut engine = self.engine.lock().await;
+        // Like in `App::run_until`, a failed ledger verification doesn't prevent the output
+        let verified = engine.shutdown().await;
+
+        engine.write_snapshot(output).await?;
+        if let Some(path) = self.snapshot.as_ref() {
+            let mut file = compression::create(path)?;
+            engine.write_snapshot(&mut file).await?;
+            file.finish()?;
+        }
+
+        verified
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SnapshotResponse {
+    path: PathBuf,
+    accounts: usize,
+}
+
+pub fn router(state: ServerState) -> Router {
+    Router::new()
+        .route("/transactions", post(submit_transaction))
+        .route("/accounts", get(list_accounts))
+        .route("/accounts/{client}", get(get_account))
+        .route("/snapshot", post(snapshot))
+        .with_state(state)
+}
+
+pub async fn serve(listener: TcpListener, state: ServerState) -> Result<(), Box<dyn Error>> {
+    serve_until(listener, state, shutdown::never()).await
+}
+
+/// Stops accepting connections once a stop is requested and returns after the requests in progress are answered
+pub async fn serve_until(listener: TcpListener, state: ServerState, shutdown: ShutdownReceiver) -> Result<(), Box<dyn Error>> {
+    axum::serve(listener, router(state))
+        .with_graceful_shutdown(async move {
+            requested(shutdown).await;
+        })
+        .await?;
+    Ok(())
+}
+
+fn internal_error(e: Box<dyn Error>) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+async fn submit_transaction(State(state): State<ServerState>, Json(entity): Json<TransactionEntity>) -> Result<Json<TransactionOutcome>, ApiError> {
+    let pending = state.engine.lock().await.submit_transaction(entity).await.map_err(internal_error)?;
+
+    Ok(Json(pending.outcome().await))
+}
+
+async fn list_accounts(State(state): State<ServerState>) -> Result<Json<Vec<AccountEntity>>, ApiError> {
+    let accounts = state.engine.lock().await.list_accounts().await.map_err(internal_error)?;
+
+    Ok(Json(accounts))
+}
+
+// One entity per currency of the client
+async fn get_account(State(state): State<ServerState>, Path(client): Path<u16>) -> Result<Json<Vec<AccountEntity>>, ApiError> {
+    let accounts = state.engine.lock().await.get_account(client).await
+        .map_err(|e| (StatusCode::NOT_FOUND, e.to_string()))?;
+
+    Ok(Json(accounts))
+}
+
+async fn snapshot(State(state): State<ServerState>) -> Result<Json<SnapshotResponse>, ApiError> {
+    let path = match state.snapshot.as_ref() {
+        Some(path) => path.clone(),
+        None => return Err((StatusCode::CONFLICT, "Snapshot path is not configured".to_string())),
+    };
+
+    let mut file = compression::create(&path).map_err(|e| internal_error(e.into()))?;
+    let accounts = state.engine.lock().await.write_snapshot(&mut file).await.map_err(internal_error)?;
+    file.finish().map_err(|e| internal_error(e.into()))?;
+
+    Ok(Json(SnapshotResponse { path, accounts }))
+}
@@ -0,0 +1,91 @@
+use std::io;
+
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    Interrupt,
+    Terminate,
+}
+
+impl Signal {
+    /// Conventional exit code of a process stopped by the signal, 128 + signal number
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Signal::Interrupt => 130,
+            Signal::Terminate => 143,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Signal::Interrupt => "SIGINT",
+            Signal::Terminate => "SIGTERM",
+        }
+    }
+}
+
+/// Carries the signal which asked to stop, `None` while the process should keep running
+pub type ShutdownReceiver = watch::Receiver<Option<Signal>>;
+
+/// Receiver which never asks to stop
+pub fn never() -> ShutdownReceiver {
+    watch::channel(None).1
+}
+
+/// Spawns a task publishing the first SIGINT or SIGTERM received by the process
+pub fn listen_for_signals() -> io::Result<ShutdownReceiver> {
+    let (sender, receiver) = watch::channel(None);
+
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let signal = tokio::select! {
+            _ = tokio::signal::ctrl_c() => Signal::Interrupt,
+            _ = terminate.recv() => Signal::Terminate,
+        };
+        #[cfg(not(unix))]
+        let signal = {
+            let _ = tokio::signal::ctrl_c().await;
+            Signal::Interrupt
+        };
+
+        let _ = sender.send(Some(signal));
+    });
+
+    Ok(receiver)
+}
+
+/// Waits until a stop is requested, never completes if the sender is gone without asking
+pub async fn requested(mut receiver: ShutdownReceiver) -> Signal {
+    let signal = receiver.wait_for(|signal| signal.is_some()).await.map(|signal| *signal);
+
+    match signal {
+        Ok(signal) => signal.unwrap_or(Signal::Interrupt),
+        Err(_) => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_requested() {
+        let (sender, receiver) = watch::channel(None);
+        let waiting = tokio::spawn(requested(receiver));
+
+        sender.send(Some(Signal::Terminate)).unwrap();
+        assert_eq!(waiting.await.unwrap(), Signal::Terminate);
+        assert_eq!(Signal::Terminate.exit_code(), 143);
+    }
+
+    #[tokio::test]
+    async fn test_never() {
+        let stopped = tokio::time::timeout(Duration::from_millis(10), requested(never())).await;
+        assert!(stopped.is_err());
+    }
+}
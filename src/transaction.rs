@@ -1,9 +1,10 @@
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::decimal::deserialize_option_decimal;
+use crate::currency::{Currency, DEFAULT_CURRENCY};
+use crate::decimal::deserialize_option_decimal_exact;
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -11,9 +12,14 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Transfer,
+    // Admin transaction setting the credit limit of the client to the amount
+    #[serde(rename = "credit_limit")]
+    CreditLimit,
 }
 
-#[derive(Debug, PartialEq, Default)]
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
 pub enum TransactionStatus {
     #[default]
     Normal,
@@ -22,27 +28,108 @@ pub enum TransactionStatus {
     Chargebacked,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, PartialEq)]
 pub struct TransactionEntity {
     #[serde(rename = "type")]
     pub transaction_type: TransactionType,
     pub client: u16,
     pub tx: u32,
-    #[serde(deserialize_with = "deserialize_option_decimal")]
+    #[serde(default, deserialize_with = "deserialize_option_decimal_exact")]
     pub amount: Option<Decimal>,
+    // Optional column, missing or empty values fall back to the default currency
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    // Destination of transfers
+    #[serde(default)]
+    pub to_client: Option<u16>,
+    // Unix time in seconds
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// Position in the intake of the engine, assigned by `PaymentEngine` and never read from the input
+    #[serde(skip)]
+    pub seq: Option<u64>,
+}
+
+impl TransactionEntity {
+    /// Transaction in the default currency, without a destination, timestamp or sequence number
+    pub fn new(transaction_type: TransactionType, client: u16, tx: u32, amount: Option<Decimal>) -> Self {
+        TransactionEntity { transaction_type, client, tx, amount, currency: None, to_client: None, timestamp: None, seq: None }
+    }
+
+    pub fn currency(&self) -> &str {
+        self.currency.as_deref().unwrap_or(DEFAULT_CURRENCY)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutcomeStatus {
+    Applied,
+    Rejected,
+}
+
+/// Result of a single submitted transaction
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TransactionOutcome {
+    pub client: u16,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    pub status: OutcomeStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TransactionOutcome {
+    pub fn new(entity: &TransactionEntity, result: Result<(), String>) -> Self {
+        let (status, error) = match result {
+            Ok(()) => (OutcomeStatus::Applied, None),
+            Err(e) => (OutcomeStatus::Rejected, Some(e)),
+        };
+
+        TransactionOutcome {
+            client: entity.client,
+            tx: entity.tx,
+            transaction_type: entity.transaction_type,
+            seq: entity.seq,
+            status,
+            error,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Default)]
 pub struct Transaction {
     pub amount: Option<Decimal>,
+    pub currency: Currency,
+    pub timestamp: Option<u64>,
     pub status: TransactionStatus,
+    /// Portion currently held by open disputes
+    pub disputed: Decimal,
+    /// Portions released back by resolves and reversed by chargebacks
+    pub resolved: Decimal,
+    pub charged_back: Decimal,
+}
+
+impl Transaction {
+    /// Part of the amount which was never disputed, a transaction can't be disputed beyond its amount
+    pub fn undisputed(&self) -> Decimal {
+        self.amount.unwrap_or(Decimal::new(0, 0)) - self.disputed - self.resolved - self.charged_back
+    }
 }
 
 impl From<&TransactionEntity> for Transaction {
     fn from(entity: &TransactionEntity) -> Self {
         Transaction {
             amount: entity.amount,
+            currency: entity.currency().to_string(),
+            timestamp: entity.timestamp,
             status: TransactionStatus::Normal,
+            disputed: Decimal::new(0, 0),
+            resolved: Decimal::new(0, 0),
+            charged_back: Decimal::new(0, 0),
         }
     }
 }
@@ -70,36 +157,78 @@ mod tests {
 
     #[test]
     fn test_deserialize_transactions() {
-        let expected = vec![TransactionEntity {
-            transaction_type: TransactionType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(Decimal::from_str("100.00").unwrap()),
-        }, TransactionEntity {
-            transaction_type: TransactionType::Withdrawal,
-            client: 1,
-            tx: 2,
-            amount: Some(Decimal::from_str("100.00").unwrap()),
-        }, TransactionEntity {
-            transaction_type: TransactionType::Dispute,
-            client: 1,
-            tx: 3,
-            amount: None,
-        }, TransactionEntity {
-            transaction_type: TransactionType::Resolve,
-            client: 1,
-            tx: 4,
-            amount: None,
-        }, TransactionEntity {
-            transaction_type: TransactionType::Chargeback,
-            client: 1,
-            tx: 5,
-            amount: None,
-        }];
+        let expected = vec![
+            TransactionEntity::new(TransactionType::Deposit, 1, 1, Some(Decimal::from_str("100.00").unwrap())),
+            TransactionEntity::new(TransactionType::Withdrawal, 1, 2, Some(Decimal::from_str("100.00").unwrap())),
+            TransactionEntity::new(TransactionType::Dispute, 1, 3, None),
+            TransactionEntity::new(TransactionType::Resolve, 1, 4, None),
+            TransactionEntity::new(TransactionType::Chargeback, 1, 5, None),
+        ];
 
         assert_eq!(deserialize_from_string("type,client,tx,amount\ndeposit,1,1,100\nwithdrawal,1,2,100\ndispute,1,3,\nresolve,1,4,\nchargeback,1,5,"), expected);
     }
 
+    #[test]
+    fn test_deserialize_transactions_with_currency() {
+        let transactions = deserialize_from_string("type,client,tx,amount,currency\ndeposit,1,1,100,EUR\nwithdrawal,1,2,100,\ndispute,1,1,,");
+
+        assert_eq!(transactions.len(), 3);
+        assert_eq!(transactions[0].currency, Some("EUR".to_string()));
+        assert_eq!(transactions[0].currency(), "EUR");
+        assert_eq!(transactions[1].currency, None);
+        assert_eq!(transactions[1].currency(), DEFAULT_CURRENCY);
+        assert_eq!(transactions[2].currency, None);
+    }
+
+    #[test]
+    fn test_deserialize_transfer() {
+        let transactions = deserialize_from_string("type,client,tx,amount,to_client\ntransfer,1,1,10.5,2\ndeposit,1,2,1,");
+
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].transaction_type, TransactionType::Transfer);
+        assert_eq!(transactions[0].to_client, Some(2));
+        assert_eq!(transactions[1].to_client, None);
+    }
+
+    #[test]
+    fn test_deserialize_timestamp() {
+        let transactions = deserialize_from_string("type,client,tx,amount,timestamp\ndeposit,1,1,10,1700000000\ndispute,1,1,,");
+
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].timestamp, Some(1700000000));
+        assert_eq!(Transaction::from(&transactions[0]).timestamp, Some(1700000000));
+        assert_eq!(transactions[1].timestamp, None);
+    }
+
+    #[test]
+    fn test_deserialize_json() {
+        let deposit: TransactionEntity = serde_json::from_str(r#"{"type":"deposit","client":1,"tx":1,"amount":"1.5"}"#).unwrap();
+        let dispute: TransactionEntity = serde_json::from_str(r#"{"type":"dispute","client":1,"tx":1}"#).unwrap();
+        let resolve: TransactionEntity = serde_json::from_str(r#"{"type":"resolve","client":1,"tx":1,"amount":null}"#).unwrap();
+
+        assert_eq!(deposit.amount, Some(Decimal::from_str("1.5").unwrap()));
+        assert_eq!(dispute.amount, None);
+        assert_eq!(resolve.amount, None);
+    }
+
+    #[test]
+    fn test_outcome() {
+        let entity: TransactionEntity = serde_json::from_str(r#"{"type":"withdrawal","client":1,"tx":2,"amount":"1"}"#).unwrap();
+
+        let outcome = TransactionOutcome::new(&entity, Err("Insufficient funds".to_string()));
+        assert_eq!(
+            serde_json::to_string(&outcome).unwrap(),
+            r#"{"client":1,"tx":2,"type":"withdrawal","status":"rejected","error":"Insufficient funds"}"#
+        );
+        assert_eq!(TransactionOutcome::new(&entity, Ok(())).status, OutcomeStatus::Applied);
+
+        let sequenced = TransactionEntity { seq: Some(7), ..entity };
+        assert_eq!(
+            serde_json::to_string(&TransactionOutcome::new(&sequenced, Ok(()))).unwrap(),
+            r#"{"client":1,"tx":2,"type":"withdrawal","seq":7,"status":"applied"}"#
+        );
+    }
+
     #[test]
     fn test_deserialize_transaction_with_invalid_type() {
         let input = "type,client,tx,amount\ntest,1,1,100";
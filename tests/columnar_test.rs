@@ -0,0 +1,116 @@
+#![cfg(feature = "columnar")]
+
+use std::fs::File;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use arrow_array::{Array, BooleanArray, Decimal128Array, RecordBatch, StringArray, UInt16Array, UInt64Array};
+use arrow_ipc::reader::FileReader;
+use arrow_schema::DataType;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use payment_engine::columnar::ColumnarOutput;
+use payment_engine::config::{AppConfig, EngineConfig};
+use payment_engine::precision::{Precision, PrecisionConfig};
+use payment_engine::App;
+use rust_decimal::RoundingStrategy;
+
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("payment_engine_{}_{}", std::process::id(), name))
+}
+
+fn read_parquet(path: &PathBuf) -> RecordBatch {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap().build().unwrap();
+    let mut batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+    assert_eq!(batches.len(), 1);
+    batches.remove(0)
+}
+
+fn read_arrow(path: &PathBuf) -> RecordBatch {
+    let reader = FileReader::try_new(File::open(path).unwrap(), None).unwrap();
+    let mut batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+    assert_eq!(batches.len(), 1);
+    batches.remove(0)
+}
+
+fn column<'a, T: 'static>(batch: &'a RecordBatch, name: &str) -> &'a T {
+    batch.column_by_name(name).unwrap().as_any().downcast_ref::<T>().unwrap()
+}
+
+#[tokio::test]
+async fn test_accounts_and_history_files() {
+    let input = "type,client,tx,amount,currency,to_client\n\
+        deposit,1,1,10.1234,USD,\n\
+        deposit,2,2,3,EUR,\n\
+        transfer,1,3,2.5,USD,2\n\
+        dispute,2,2,,EUR,\n";
+
+    for (accounts_name, history_name) in [("accounts.parquet", "history.arrow"), ("accounts.arrow", "history.parquet")] {
+        let accounts_path = temp_file(accounts_name);
+        let history_path = temp_file(history_name);
+        let config = AppConfig {
+            engine: EngineConfig { history: true, ..Default::default() },
+            ordered_output: true,
+            columnar: Some(ColumnarOutput { accounts: Some(accounts_path.clone()), history: Some(history_path.clone()) }),
+            ..Default::default()
+        };
+        let mut output = Cursor::new(Vec::new());
+        App::run_with_config(input.as_bytes(), &mut output, config).await.unwrap();
+
+        let (accounts, history) = if accounts_name.ends_with(".parquet") {
+            (read_parquet(&accounts_path), read_arrow(&history_path))
+        } else {
+            (read_arrow(&accounts_path), read_parquet(&history_path))
+        };
+        std::fs::remove_file(&accounts_path).unwrap();
+        std::fs::remove_file(&history_path).unwrap();
+
+        assert_eq!(accounts.schema().field_with_name("available").unwrap().data_type(), &DataType::Decimal128(38, 4));
+        assert_eq!(column::<UInt16Array>(&accounts, "client").values().to_vec(), [1, 2, 2]);
+        let currencies = column::<StringArray>(&accounts, "currency");
+        assert_eq!((currencies.value(0), currencies.value(1), currencies.value(2)), ("USD", "EUR", "USD"));
+        assert_eq!(column::<Decimal128Array>(&accounts, "available").values().to_vec(), [76234, 0, 25000]);
+        assert_eq!(column::<Decimal128Array>(&accounts, "held").value_as_string(1), "3.0000");
+        assert!(column::<Decimal128Array>(&accounts, "fees").is_null(0));
+        assert!(!column::<BooleanArray>(&accounts, "locked").value(0));
+
+        // The transfer has an entry in both accounts
+        assert_eq!(history.num_rows(), 5);
+        assert_eq!(column::<UInt64Array>(&history, "seq").values().to_vec(), [1, 2, 3, 3, 4]);
+        assert_eq!(column::<StringArray>(&history, "type").value(4), "dispute");
+        assert_eq!(column::<StringArray>(&history, "status_after").value(4), "disputed");
+        assert_eq!(column::<Decimal128Array>(&history, "total_after").value_as_string(0), "10.1234");
+    }
+}
+
+#[tokio::test]
+async fn test_scale_of_configured_precision() {
+    let input = "type,client,tx,amount,currency\ndeposit,1,1,0.12345678,BTC\ndeposit,1,2,1.5,USD\n";
+    let accounts_path = temp_file("btc_accounts.parquet");
+    let precision = PrecisionConfig::default().with_currency("BTC", Precision::new(8, RoundingStrategy::ToZero));
+    let config = AppConfig {
+        engine: EngineConfig { precision, ..Default::default() },
+        ordered_output: true,
+        columnar: Some(ColumnarOutput { accounts: Some(accounts_path.clone()), history: None }),
+        ..Default::default()
+    };
+    App::run_with_config(input.as_bytes(), Vec::new(), config).await.unwrap();
+
+    let accounts = read_parquet(&accounts_path);
+    std::fs::remove_file(&accounts_path).unwrap();
+
+    assert_eq!(accounts.schema().field_with_name("total").unwrap().data_type(), &DataType::Decimal128(38, 8));
+    let total = column::<Decimal128Array>(&accounts, "total");
+    assert_eq!((total.value_as_string(0), total.value_as_string(1)), ("0.12345678".to_string(), "1.50000000".to_string()));
+}
+
+#[tokio::test]
+async fn test_csv_output_is_written_when_columnar_fails() {
+    let config = AppConfig {
+        columnar: Some(ColumnarOutput { accounts: Some(temp_file("missing").join("accounts.parquet")), history: None }),
+        ..Default::default()
+    };
+    let mut output = Cursor::new(Vec::new());
+    assert!(App::run_with_config("type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes(), &mut output, config).await.is_err());
+
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), "client,available,held,total,locked\n1,1.0,0,1.0,false\n");
+}
@@ -1,6 +1,23 @@
 use std::io::Cursor;
-use payment_engine::App;
-use tokio;
+use payment_engine::{App, RunStatus};
+use payment_engine::shutdown::Signal;
+use payment_engine::compression::{self, Compression, Encoder};
+use payment_engine::config::{AppConfig, EngineConfig, EngineMode, InputFormat, ParallelParsing};
+use payment_engine::generator::{Generator, GeneratorConfig, TypeWeights};
+use payment_engine::credit::load_credit_limits;
+use payment_engine::history::StatementFormat;
+use payment_engine::fees::FeeSchedule;
+use payment_engine::ledger::LedgerAccount;
+use payment_engine::limits::{Limits, LimitsConfig};
+use payment_engine::precision::{Precision, PrecisionConfig, PrecisionPolicy};
+use payment_engine::payment_engine::PaymentEngine;
+use payment_engine::transaction::{TransactionEntity, TransactionType};
+use rust_decimal::RoundingStrategy;
+use rust_decimal_macros::dec;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::io::Read;
+use tokio::sync::watch;
 
 async fn process_csv_string(csv_content: &str) -> String {
     let mut output = Cursor::new(Vec::new());
@@ -175,4 +192,820 @@ client,available,held,total,locked
 ";
 
     assert_eq!(process_csv_string(csv_content).await, expected_accounts_csv);
-}   
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_export_statement() {
+    let mut engine = PaymentEngine::with_config(EngineConfig { history: true, ..Default::default() });
+
+    let transactions = vec![
+        TransactionEntity::new(TransactionType::Deposit, 1, 1, Some(dec!(100.0))),
+        TransactionEntity::new(TransactionType::Deposit, 2, 2, Some(dec!(5.0))),
+        TransactionEntity::new(TransactionType::Withdrawal, 1, 3, Some(dec!(200.0))),
+        TransactionEntity::new(TransactionType::Dispute, 1, 1, None),
+        TransactionEntity::new(TransactionType::Resolve, 1, 1, None),
+    ];
+
+    for transaction in transactions {
+        engine.process_transaction(transaction).await.unwrap();
+    }
+    engine.shutdown().await.unwrap();
+
+    let expected_statement = "\
+seq,client,tx,type,currency,available_before,held_before,total_before,available_after,held_after,total_after,status_before,status_after,locked
+1,1,1,deposit,USD,0,0,0,100.0,0,100.0,,normal,false
+4,1,1,dispute,USD,100.0,0,100.0,0.0,100.0,100.0,normal,disputed,false
+5,1,1,resolve,USD,0.0,100.0,100.0,100.0,0.0,100.0,disputed,resolved,false
+";
+
+    let mut output = Vec::new();
+    engine.export_statement(1, StatementFormat::Csv, &mut output).await.unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), expected_statement);
+
+    assert!(engine.export_statement(3, StatementFormat::Csv, Vec::new()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_query_running_engine() {
+    let mut engine = PaymentEngine::with_config(EngineConfig { history: true, ..Default::default() });
+
+    engine.process_transaction(TransactionEntity::new(TransactionType::Deposit, 1, 1, Some(dec!(100.0)))).await.unwrap();
+    engine.process_transaction(TransactionEntity::new(TransactionType::Withdrawal, 1, 2, Some(dec!(40.0)))).await.unwrap();
+
+    let accounts = engine.get_account(1).await.unwrap();
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].available, dec!(60.0));
+
+    engine.process_transaction(TransactionEntity::new(TransactionType::Deposit, 1, 3, Some(dec!(5.0)))).await.unwrap();
+    engine.process_transaction(TransactionEntity::new(TransactionType::Dispute, 1, 3, None)).await.unwrap();
+    assert_eq!(engine.get_account(1).await.unwrap()[0].total, dec!(65.0));
+    assert!(engine.get_account(2).await.is_err());
+
+    // The transactions got the sequence numbers 1 to 4 in the order they were sent
+    assert_eq!(engine.get_account_after(1, 1).await.unwrap().total, dec!(100.0));
+    assert_eq!(engine.get_account_after(1, 2).await.unwrap().total, dec!(60.0));
+    assert!(engine.get_account_after(1, 0).await.is_err());
+
+    engine.shutdown().await.unwrap();
+
+    let account = engine.get_account(1).await.unwrap();
+    assert_eq!((account[0].available, account[0].held), (dec!(60.0), dec!(5.0)));
+    let after_deposit = engine.get_account_after(1, 3).await.unwrap();
+    assert_eq!((after_deposit.available, after_deposit.held), (dec!(65.0), dec!(0)));
+    let after_dispute = engine.get_account_after(1, 4).await.unwrap();
+    assert_eq!((after_dispute.available, after_dispute.held), (dec!(60.0), dec!(5.0)));
+}
+
+#[tokio::test]
+async fn test_export_statement_without_history() {
+    let mut engine = PaymentEngine::new();
+    engine.process_transaction(TransactionEntity::new(TransactionType::Deposit, 1, 1, Some(dec!(1.0)))).await.unwrap();
+    engine.shutdown().await.unwrap();
+
+    assert!(engine.export_statement(1, StatementFormat::Jsonl, Vec::new()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_double_entry_ledger_balances() {
+    let mut engine = PaymentEngine::with_config(EngineConfig { double_entry: true, ..Default::default() });
+
+    let transactions = vec![
+        TransactionEntity::new(TransactionType::Deposit, 1, 1, Some(dec!(100.0))),
+        TransactionEntity::new(TransactionType::Deposit, 2, 2, Some(dec!(50.0))),
+        TransactionEntity::new(TransactionType::Withdrawal, 2, 3, Some(dec!(20.0))),
+        TransactionEntity::new(TransactionType::Dispute, 1, 1, None),
+        TransactionEntity::new(TransactionType::Chargeback, 1, 1, None),
+    ];
+
+    for transaction in transactions {
+        engine.process_transaction(transaction).await.unwrap();
+    }
+    engine.shutdown().await.unwrap();
+
+    let balances = engine.verify_ledger().await.unwrap();
+    assert_eq!(balances[&(LedgerAccount::Settlement, "USD".to_string())], dec!(-130.0));
+    assert_eq!(balances[&(LedgerAccount::ChargebackLoss, "USD".to_string())], dec!(100.0));
+    assert_eq!(balances[&(LedgerAccount::ClientAvailable(1), "USD".to_string())], dec!(0));
+    assert_eq!(balances[&(LedgerAccount::ClientAvailable(2), "USD".to_string())], dec!(30.0));
+}
+
+#[tokio::test]
+async fn test_invariants_hold_for_dispute_flow() {
+    let csv_content = "\
+type,client,tx,amount
+deposit,1,1,100.0
+deposit,1,2,30.0
+withdrawal,1,3,20.0
+dispute,1,2,
+dispute,1,1,
+resolve,1,1,
+resolve,1,1,
+chargeback,1,2,
+deposit,1,4,10.0";
+
+    let expected_accounts_csv = "\
+client,available,held,total,locked
+1,80.0,0.0,80.0,true
+";
+
+    let config = AppConfig {
+        engine: EngineConfig { check_invariants: true, double_entry: true, ..Default::default() },
+        ordered_output: true,
+        ..Default::default()
+    };
+    let mut output = Cursor::new(Vec::new());
+    App::run_with_config(csv_content.as_bytes(), &mut output, config).await.unwrap();
+
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), expected_accounts_csv);
+}
+
+#[tokio::test]
+async fn test_multi_currency_flow() {
+    let csv_content = "\
+type,client,tx,amount,currency
+deposit,1,1,100.0,EUR
+deposit,1,2,50.0
+deposit,2,3,10.0,GBP
+withdrawal,1,4,60.0,USD
+withdrawal,1,5,60.0,EUR
+dispute,1,1,
+dispute,1,2,
+deposit,1,6,1.0,
+deposit,2,4,5.0,";
+
+    let expected_accounts_csv = "\
+client,currency,available,held,total,locked
+1,EUR,40.0,0,40.0,false
+1,USD,1.0,50.0,51.0,false
+2,GBP,10.0,0,10.0,false
+2,USD,5.0,0,5.0,false
+";
+
+    assert_eq!(process_csv_string(csv_content).await, expected_accounts_csv);
+}
+
+#[tokio::test]
+async fn test_precision_per_currency() {
+    let csv_content = "\
+type,client,tx,amount,currency
+deposit,1,1,1.23456789,USD
+deposit,1,2,0.123456789,BTC
+deposit,1,3,1500.5,JPY
+withdrawal,1,4,0.5,JPY";
+
+    let expected_accounts_csv = "\
+client,currency,available,held,total,locked
+1,BTC,0.12345679,0,0.12345679,false
+1,JPY,1500,0,1500,false
+1,USD,1.2345,0,1.2345,false
+";
+
+    let precision = PrecisionConfig::default()
+        .with_currency("JPY", Precision::new(0, RoundingStrategy::ToZero))
+        .with_currency("BTC", Precision::new(8, RoundingStrategy::MidpointAwayFromZero));
+    let config = AppConfig {
+        engine: EngineConfig { precision, ..Default::default() },
+        ordered_output: true,
+        ..Default::default()
+    };
+
+    let mut output = Cursor::new(Vec::new());
+    App::run_with_config(csv_content.as_bytes(), &mut output, config).await.unwrap();
+
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), expected_accounts_csv);
+}
+
+// What `--strict` sets
+fn strict_precision() -> PrecisionConfig {
+    PrecisionConfig { policy: PrecisionPolicy::Reject, ..Default::default() }
+}
+
+#[tokio::test]
+async fn test_strict_parse_mode_rejects_over_precise_amounts() {
+    let csv_content = "\
+type,client,tx,amount
+deposit,1,1,1.23456789
+deposit,1,2,2.5000
+deposit,2,3,1.00000
+withdrawal,1,4,0.12345";
+
+    let expected_accounts_csv = "\
+client,available,held,total,locked
+1,2.5000,0,2.5000,false
+2,1.0000,0,1.0000,false
+";
+
+    let config = AppConfig {
+        ordered_output: true,
+        engine: EngineConfig { precision: strict_precision(), ..Default::default() },
+        ..Default::default()
+    };
+
+    let mut output = Cursor::new(Vec::new());
+    App::run_with_config(csv_content.as_bytes(), &mut output, config).await.unwrap();
+
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), expected_accounts_csv);
+}
+
+#[tokio::test]
+async fn test_lenient_parse_mode_truncates_over_precise_amounts() {
+    let csv_content = "\
+type,client,tx,amount
+deposit,1,1,1.23456789
+withdrawal,1,2,0.12345";
+
+    let expected_accounts_csv = "\
+client,available,held,total,locked
+1,1.1111,0,1.1111,false
+";
+
+    assert_eq!(process_csv_string(csv_content).await, expected_accounts_csv);
+}
+
+#[tokio::test]
+async fn test_fees_reported_separately() {
+    let csv_content = "\
+type,client,tx,amount
+deposit,1,1,100.0
+withdrawal,1,2,50.0
+deposit,2,3,10.0
+dispute,2,3,
+chargeback,2,3,";
+
+    let expected_accounts_csv = "\
+client,available,held,total,fees,locked
+1,48.75,0,48.75,1.25,false
+2,-5.0,0.0,-5.0,5.0,true
+";
+
+    let fees = FeeSchedule {
+        withdrawal_fixed: dec!(0.25),
+        withdrawal_percent: dec!(2),
+        chargeback_penalty: dec!(5.0),
+    };
+    let config = AppConfig {
+        engine: EngineConfig { fees: Some(fees), double_entry: true, ..Default::default() },
+        ordered_output: true,
+        ..Default::default()
+    };
+
+    let mut output = Cursor::new(Vec::new());
+    App::run_with_config(csv_content.as_bytes(), &mut output, config).await.unwrap();
+
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), expected_accounts_csv);
+}
+
+#[tokio::test]
+async fn test_fee_income() {
+    let fees = FeeSchedule { withdrawal_fixed: dec!(1.0), ..Default::default() };
+    let mut engine = PaymentEngine::with_config(EngineConfig { fees: Some(fees), ..Default::default() });
+
+    engine.process_transaction(TransactionEntity::new(TransactionType::Deposit, 1, 1, Some(dec!(10.0)))).await.unwrap();
+    engine.process_transaction(TransactionEntity::new(TransactionType::Withdrawal, 1, 2, Some(dec!(2.0)))).await.unwrap();
+    engine.process_transaction(TransactionEntity::new(TransactionType::Deposit, 2, 3, Some(dec!(10.0)))).await.unwrap();
+    engine.process_transaction(TransactionEntity::new(TransactionType::Withdrawal, 2, 4, Some(dec!(9.5)))).await.unwrap();
+    engine.shutdown().await.unwrap();
+
+    assert_eq!(engine.fee_income().await["USD"], dec!(1.0));
+}
+
+#[tokio::test]
+async fn test_transfers() {
+    let csv_content = "\
+type,client,tx,amount,to_client
+deposit,1,1,100.0,
+deposit,2,2,10.0,
+transfer,1,3,30.0,2
+transfer,2,4,50.0,1
+transfer,1,5,10.0,1
+transfer,1,6,10.0,
+dispute,2,2,
+chargeback,2,2,
+transfer,1,7,20.0,2
+transfer,1,8,5.0,3";
+
+    let expected_accounts_csv = "\
+client,available,held,total,locked
+1,65.0,0,65.0,false
+2,30.0,0.0,30.0,true
+3,5.0,0,5.0,false
+";
+
+    let config = AppConfig {
+        engine: EngineConfig { double_entry: true, check_invariants: true, ..Default::default() },
+        ordered_output: true,
+        ..Default::default()
+    };
+
+    let mut output = Cursor::new(Vec::new());
+    App::run_with_config(csv_content.as_bytes(), &mut output, config).await.unwrap();
+
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), expected_accounts_csv);
+}
+
+#[tokio::test]
+async fn test_transfers_with_busy_accounts() {
+    let mut engine = PaymentEngine::with_config(EngineConfig { double_entry: true, ..Default::default() });
+    let clients = 10u16;
+
+    let mut tx = 0;
+    for client in 1..=clients {
+        tx += 1;
+        engine.process_transaction(TransactionEntity::new(TransactionType::Deposit, client, tx, Some(dec!(1000)))).await.unwrap();
+    }
+
+    // Every client sends 1 to the next one while depositing and withdrawing in between
+    for round in 0..100 {
+        for client in 1..=clients {
+            tx += 1;
+            let mut transfer = TransactionEntity::new(TransactionType::Transfer, client, tx, Some(dec!(1)));
+            transfer.to_client = Some(client % clients + 1);
+            engine.process_transaction(transfer).await.unwrap();
+
+            tx += 1;
+            let transaction_type = if round % 2 == 0 { TransactionType::Deposit } else { TransactionType::Withdrawal };
+            engine.process_transaction(TransactionEntity::new(transaction_type, client, tx, Some(dec!(0.5)))).await.unwrap();
+        }
+    }
+    engine.shutdown().await.unwrap();
+
+    for account in engine.get_account_entities(true).await {
+        assert_eq!(account.total, dec!(1000));
+    }
+
+    let balances = engine.verify_ledger().await.unwrap();
+    assert_eq!(balances[&(LedgerAccount::TransfersInTransit, "USD".to_string())], dec!(0));
+}
+
+#[tokio::test]
+async fn test_credit_limits() {
+    let csv_content = "\
+type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,30.0
+withdrawal,1,3,30.0
+credit_limit,2,4,5.0
+withdrawal,2,5,5.0
+deposit,3,6,1.0
+withdrawal,3,7,2.0";
+
+    let expected_accounts_csv = "\
+client,available,held,total,credit_limit,remaining_credit,locked
+1,-20.0,0,-20.0,25.0,5.0,false
+2,-5.0,0,-5.0,5.0,0.0,false
+3,1.0,0,1.0,0,0,false
+";
+
+    let limits = load_credit_limits("client,limit\n1,25.0".as_bytes()).unwrap();
+    let config = AppConfig {
+        engine: EngineConfig { credit_limits: Some(limits), check_invariants: true, ..Default::default() },
+        ordered_output: true,
+        ..Default::default()
+    };
+
+    let mut output = Cursor::new(Vec::new());
+    App::run_with_config(csv_content.as_bytes(), &mut output, config).await.unwrap();
+
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), expected_accounts_csv);
+}
+
+#[tokio::test]
+async fn test_limits_per_client() {
+    let csv_content = "\
+type,client,tx,amount
+deposit,1,1,500.0
+deposit,1,2,100.0
+withdrawal,1,3,60.0
+withdrawal,1,4,40.0
+deposit,2,5,500.0
+withdrawal,2,6,300.0";
+
+    let expected_accounts_csv = "\
+client,available,held,total,locked
+1,60.0,0,60.0,false
+2,200.0,0,200.0,false
+";
+
+    let limits = LimitsConfig {
+        global: HashMap::from([("USD".to_string(), Limits { max_deposit: Some(dec!(200)), max_withdrawal: Some(dec!(50)), ..Default::default() })]),
+        clients: HashMap::from([(2, HashMap::from([("USD".to_string(), Limits { max_deposit: Some(dec!(1000)), max_withdrawal: Some(dec!(1000)), ..Default::default() })]))]),
+    };
+    let config = AppConfig {
+        engine: EngineConfig { limits, ..Default::default() },
+        ordered_output: true,
+        ..Default::default()
+    };
+
+    let mut output = Cursor::new(Vec::new());
+    App::run_with_config(csv_content.as_bytes(), &mut output, config).await.unwrap();
+
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), expected_accounts_csv);
+}
+
+#[tokio::test]
+async fn test_dispute_window_and_timestamps() {
+    let csv_content = "\
+type,client,tx,amount,timestamp
+deposit,1,1,10.0,0
+deposit,1,2,20.0,864000
+deposit,2,3,5.0,100
+deposit,2,4,5.0,50
+dispute,1,1,,10454400
+dispute,1,2,,10454400
+dispute,2,3,,";
+
+    let expected_accounts_csv = "\
+client,available,held,total,locked
+1,10.0,20.0,30.0,false
+2,0.0,5.0,5.0,false
+";
+
+    let config = AppConfig {
+        engine: EngineConfig { dispute_window: Some(Duration::from_secs(120 * 24 * 60 * 60)), ..Default::default() },
+        ordered_output: true,
+        ..Default::default()
+    };
+
+    let mut output = Cursor::new(Vec::new());
+    App::run_with_config(csv_content.as_bytes(), &mut output, config).await.unwrap();
+
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), expected_accounts_csv);
+}
+
+#[tokio::test]
+async fn test_partial_disputes() {
+    let csv_content = "\
+type,client,tx,amount
+deposit,1,1,100.0
+dispute,1,1,40.0
+dispute,1,1,30.0
+resolve,1,1,10.0
+chargeback,1,1,20.0
+deposit,2,2,50.0
+dispute,2,2,60.0
+dispute,2,2,20.0
+resolve,2,2,";
+
+    let expected_accounts_csv = "\
+client,available,held,total,locked
+1,40.0,40.0,80.0,true
+2,50.0,0.0,50.0,false
+";
+
+    assert_eq!(process_csv_string(csv_content).await, expected_accounts_csv);
+}
+
+// Serves the first chunk, then asks to stop before serving the rest
+struct SignalAfterFirstChunk {
+    chunks: Vec<&'static [u8]>,
+    sender: watch::Sender<Option<Signal>>,
+}
+
+impl Read for SignalAfterFirstChunk {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.chunks.len() == 1 {
+            self.sender.send(Some(Signal::Terminate)).unwrap();
+        }
+        if self.chunks.is_empty() {
+            return Ok(0);
+        }
+
+        let chunk = self.chunks[0];
+        let read = chunk.len().min(buf.len());
+        buf[..read].copy_from_slice(&chunk[..read]);
+        if read == chunk.len() {
+            self.chunks.remove(0);
+        } else {
+            self.chunks[0] = &chunk[read..];
+        }
+        Ok(read)
+    }
+}
+
+#[tokio::test]
+async fn test_interrupted_run_writes_output_and_snapshot() {
+    let (sender, receiver) = watch::channel(None);
+    let input = SignalAfterFirstChunk {
+        chunks: vec![
+            b"type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,5.0\n",
+            b"deposit,2,3,1.0\ndeposit,1,4,1.0\n",
+        ],
+        sender,
+    };
+
+    let snapshot = std::env::temp_dir().join(format!("payment_engine_interrupted_{}.csv", std::process::id()));
+    let config = AppConfig { ordered_output: true, snapshot: Some(snapshot.clone()), ..Default::default() };
+
+    let mut output = Cursor::new(Vec::new());
+    let status = App::run_until(input, &mut output, config, receiver).await.unwrap();
+
+    assert_eq!(status, RunStatus::Interrupted(Signal::Terminate));
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), "client,available,held,total,locked\n1,15.0,0,15.0,false\n");
+
+    let snapshot_content = std::fs::read_to_string(&snapshot).unwrap();
+    std::fs::remove_file(&snapshot).unwrap();
+    assert_eq!(snapshot_content, "client,currency,available,held,total,locked\n1,USD,15.0,0,15.0,false\n");
+}
+
+#[tokio::test]
+async fn test_output_is_written_when_snapshot_fails() {
+    let snapshot = std::env::temp_dir().join(format!("payment_engine_missing_{}", std::process::id())).join("snapshot.csv");
+    let config = AppConfig { snapshot: Some(snapshot), ..Default::default() };
+
+    let mut output = Cursor::new(Vec::new());
+    assert!(App::run_with_config("type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes(), &mut output, config).await.is_err());
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), "client,available,held,total,locked\n1,1.0,0,1.0,false\n");
+}
+
+#[tokio::test]
+async fn test_engine_rejects_transactions_after_shutdown() {
+    let mut engine = PaymentEngine::new();
+    engine.process_transaction(TransactionEntity::new(TransactionType::Deposit, 1, 1, Some(dec!(1.0)))).await.unwrap();
+    engine.shutdown().await.unwrap();
+
+    assert!(engine.is_stopped());
+    assert!(engine.process_transaction(TransactionEntity::new(TransactionType::Deposit, 2, 2, Some(dec!(1.0)))).await.is_err());
+    assert!(engine.submit_transaction(TransactionEntity::new(TransactionType::Deposit, 1, 3, Some(dec!(1.0)))).await.is_err());
+    engine.shutdown().await.unwrap();
+    assert_eq!(engine.list_accounts().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_jsonl_input() {
+    let jsonl_content = r#"{"type":"deposit","client":1,"tx":1,"amount":"10.5"}
+{"type":"withdrawal","client":1,"tx":2,"amount":"0.5"}
+
+{"type":"deposit","client":2,"tx":3,"amount":"3"}
+{"type":"dispute","client":2,"tx":3}
+{"type":"bogus","client":2,"tx":4}
+"#;
+
+    let expected_accounts_csv = "\
+client,available,held,total,locked
+1,10.0,0,10.0,false
+2,0,3,3,false
+";
+
+    let config = AppConfig { input_format: InputFormat::Jsonl, ordered_output: true, ..Default::default() };
+    let mut output = Cursor::new(Vec::new());
+    App::run_with_config(jsonl_content.as_bytes(), &mut output, config).await.unwrap();
+
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), expected_accounts_csv);
+}
+
+#[tokio::test]
+async fn test_generated_csv_and_jsonl_give_same_accounts() {
+    let generator_config = GeneratorConfig {
+        rows: 5000,
+        clients: 50,
+        seed: 42,
+        weights: TypeWeights { transfer: 5, ..Default::default() },
+        partial_dispute_ratio: 0.3,
+        invalid_ratio: 0.01,
+        ..Default::default()
+    };
+
+    let mut outputs = Vec::new();
+    for format in [InputFormat::Csv, InputFormat::Jsonl] {
+        let mut input = Vec::new();
+        Generator::new(generator_config.clone()).write(format, &mut input).unwrap();
+
+        let config = AppConfig { input_format: format, ordered_output: true, ..Default::default() };
+        let mut output = Cursor::new(Vec::new());
+        App::run_with_config(input.as_slice(), &mut output, config).await.unwrap();
+        outputs.push(String::from_utf8(output.into_inner()).unwrap());
+    }
+
+    assert_eq!(outputs[0].lines().count(), 51);
+    assert_eq!(outputs[0], outputs[1]);
+}
+
+#[tokio::test]
+async fn test_engine_modes_give_same_accounts() {
+    let generator_config = GeneratorConfig {
+        rows: 5000,
+        clients: 50,
+        seed: 7,
+        weights: TypeWeights { transfer: 5, ..Default::default() },
+        partial_dispute_ratio: 0.3,
+        ..Default::default()
+    };
+    let mut input = Vec::new();
+    Generator::new(generator_config).write(InputFormat::Csv, &mut input).unwrap();
+
+    let modes = [
+        (EngineMode::ActorPerClient, None),
+        (EngineMode::Sharded(4), Some(1)),
+        (EngineMode::Synchronous, None),
+        // Taken as a capacity of 1
+        (EngineMode::ActorPerClient, Some(0)),
+    ];
+
+    let mut outputs = Vec::new();
+    for (mode, channel_size) in modes {
+        let config = AppConfig {
+            engine: EngineConfig { mode, channel_size, double_entry: true, check_invariants: true, ..Default::default() },
+            ordered_output: true,
+            ..Default::default()
+        };
+        let mut output = Cursor::new(Vec::new());
+        App::run_with_config(input.as_slice(), &mut output, config).await.unwrap();
+        outputs.push(String::from_utf8(output.into_inner()).unwrap());
+    }
+
+    assert_eq!(outputs[0].lines().count(), 51);
+    assert_eq!(outputs[0], outputs[1]);
+    assert_eq!(outputs[0], outputs[2]);
+    assert_eq!(outputs[0], outputs[3]);
+}
+
+#[tokio::test]
+async fn test_query_engine_modes() {
+    for mode in [EngineMode::Sharded(2), EngineMode::Synchronous] {
+        let mut engine = PaymentEngine::with_config(EngineConfig { mode, ..Default::default() });
+        engine.process_transaction(TransactionEntity::new(TransactionType::Deposit, 1, 1, Some(dec!(5.0)))).await.unwrap();
+        engine.process_transaction(TransactionEntity::new(TransactionType::Deposit, 3, 2, Some(dec!(2.0)))).await.unwrap();
+        engine.process_transaction(TransactionEntity::new(TransactionType::Withdrawal, 1, 3, Some(dec!(1.5)))).await.unwrap();
+
+        let account = engine.get_account(1).await.unwrap();
+        assert_eq!(account[0].available, dec!(3.5));
+        assert_eq!(engine.get_account(3).await.unwrap()[0].available, dec!(2.0));
+        assert!(engine.get_account(2).await.is_err());
+
+        engine.shutdown().await.unwrap();
+        assert_eq!(engine.list_accounts().await.unwrap().len(), 2);
+    }
+}
+
+#[tokio::test]
+async fn test_parallel_parsing_gives_same_accounts() {
+    let generator_config = GeneratorConfig {
+        rows: 20_000,
+        clients: 300,
+        seed: 5,
+        weights: TypeWeights { transfer: 5, ..Default::default() },
+        partial_dispute_ratio: 0.3,
+        invalid_ratio: 0.01,
+        ..Default::default()
+    };
+    let mut input = Vec::new();
+    Generator::new(generator_config).write(InputFormat::Csv, &mut input).unwrap();
+
+    let parsings = [
+        None,
+        Some(ParallelParsing { threads: 3, chunk_size: 64 }),
+        Some(ParallelParsing { threads: 8, chunk_size: 4096 }),
+    ];
+
+    let mut outputs = Vec::new();
+    for parallel_parsing in parsings {
+        let config = AppConfig {
+            engine: EngineConfig { mode: EngineMode::Sharded(4), ..Default::default() },
+            parallel_parsing,
+            ordered_output: true,
+            ..Default::default()
+        };
+        let mut output = Cursor::new(Vec::new());
+        App::run_with_config(input.as_slice(), &mut output, config).await.unwrap();
+        outputs.push(String::from_utf8(output.into_inner()).unwrap());
+    }
+
+    assert_eq!(outputs[0].lines().count(), 301);
+    assert_eq!(outputs[0], outputs[1]);
+    assert_eq!(outputs[0], outputs[2]);
+}
+
+#[tokio::test]
+async fn test_parallel_parsing_strict_mode_and_unterminated_last_row() {
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        deposit,1,2,1.00001\n\
+        withdrawal,1,3,0.5";
+
+    let config = AppConfig {
+        engine: EngineConfig { precision: strict_precision(), ..Default::default() },
+        parallel_parsing: Some(ParallelParsing { threads: 2, chunk_size: 8 }),
+        ..Default::default()
+    };
+    let mut output = Cursor::new(Vec::new());
+    App::run_with_config(input.as_bytes(), &mut output, config).await.unwrap();
+
+    assert_eq!(String::from_utf8(output.into_inner()).unwrap(), "client,available,held,total,locked\n1,0.5,0,0.5,false\n");
+}
+
+#[tokio::test]
+async fn test_malformed_rows_are_skipped() {
+    let input = b"type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        deposit,1,2,\xff\n\
+        deposit,1,\x80,1.0\n\
+        deposit,1,3,2.0\n";
+
+    for parallel_parsing in [None, Some(ParallelParsing { threads: 2, chunk_size: 16 })] {
+        let config = AppConfig { parallel_parsing, ..Default::default() };
+        let mut output = Cursor::new(Vec::new());
+        App::run_with_config(&input[..], &mut output, config).await.unwrap();
+
+        assert_eq!(String::from_utf8(output.into_inner()).unwrap(), "client,available,held,total,locked\n1,3.0,0,3.0,false\n");
+    }
+}
+
+#[tokio::test]
+async fn test_run_can_be_spawned() {
+    let input = compress(b"type,client,tx,amount\ndeposit,1,1,1.0\n", Compression::Gzip);
+    let run = tokio::spawn(async move {
+        let mut output = Vec::new();
+        App::run(input.as_slice(), &mut output, true).await.map_err(|e| e.to_string())?;
+        Ok::<_, String>(output)
+    });
+
+    assert_eq!(String::from_utf8(run.await.unwrap().unwrap()).unwrap(), "client,available,held,total,locked\n1,1.0,0,1.0,false\n");
+}
+
+#[tokio::test]
+async fn test_run_accepts_readers_which_are_not_send() {
+    let input = std::io::Cursor::new(std::rc::Rc::<[u8]>::from(&b"type,client,tx,amount\ndeposit,1,1,1.0\n"[..]));
+    let mut output = Vec::new();
+    App::run(input, &mut output, true).await.unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n1,1.0,0,1.0,false\n");
+}
+
+fn compress(data: &[u8], compression: Compression) -> Vec<u8> {
+    let mut encoder = Encoder::new(Vec::new(), Some(compression)).unwrap();
+    std::io::Write::write_all(&mut encoder, data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[tokio::test]
+async fn test_compressed_inputs_give_same_accounts() {
+    let generator_config = GeneratorConfig {
+        rows: 5000,
+        clients: 50,
+        seed: 11,
+        weights: TypeWeights { transfer: 5, ..Default::default() },
+        ..Default::default()
+    };
+
+    for format in [InputFormat::Csv, InputFormat::Jsonl] {
+        let mut input = Vec::new();
+        Generator::new(generator_config.clone()).write(format, &mut input).unwrap();
+
+        let inputs = [
+            input.clone(),
+            compress(&input, Compression::Gzip),
+            compress(&input, Compression::Zstd),
+        ];
+
+        let mut outputs = Vec::new();
+        for (index, input) in inputs.iter().enumerate() {
+            let config = AppConfig {
+                input_format: format,
+                parallel_parsing: (index == 1).then_some(ParallelParsing { threads: 2, chunk_size: 1024 }),
+                ordered_output: true,
+                ..Default::default()
+            };
+            let mut output = Cursor::new(Vec::new());
+            App::run_with_config(input.as_slice(), &mut output, config).await.unwrap();
+            outputs.push(String::from_utf8(output.into_inner()).unwrap());
+        }
+
+        assert_eq!(outputs[0].lines().count(), 51);
+        assert_eq!(outputs[0], outputs[1]);
+        assert_eq!(outputs[0], outputs[2]);
+    }
+}
+
+#[tokio::test]
+async fn test_compressed_output_and_snapshot() {
+    let input = "type,client,tx,amount\ndeposit,1,1,2.5\n";
+    let snapshot = std::env::temp_dir().join(format!("payment_engine_compressed_{}.csv.zst", std::process::id()));
+
+    let config = AppConfig {
+        output_compression: Some(Compression::Gzip),
+        snapshot: Some(snapshot.clone()),
+        ..Default::default()
+    };
+    let mut output = Cursor::new(Vec::new());
+    App::run_with_config(input.as_bytes(), &mut output, config).await.unwrap();
+
+    let output = output.into_inner();
+    assert_eq!(Compression::detect(&output), Some(Compression::Gzip));
+    let mut accounts = String::new();
+    compression::decompress(output.as_slice(), None).unwrap().read_to_string(&mut accounts).unwrap();
+    assert_eq!(accounts, "client,available,held,total,locked\n1,2.5,0,2.5,false\n");
+
+    let file = std::fs::read(&snapshot).unwrap();
+    std::fs::remove_file(&snapshot).unwrap();
+    assert_eq!(Compression::detect(&file), Some(Compression::Zstd));
+    let mut snapshot_content = String::new();
+    compression::decompress(file.as_slice(), None).unwrap().read_to_string(&mut snapshot_content).unwrap();
+    assert_eq!(snapshot_content, "client,currency,available,held,total,locked\n1,USD,2.5,0,2.5,false\n");
+}
+
+#[test]
+fn test_input_format_behind_compression_suffix() {
+    assert_eq!(InputFormat::from_path("transactions.jsonl.gz"), InputFormat::Jsonl);
+    assert_eq!(InputFormat::from_path("transactions.jsonl.zst"), InputFormat::Jsonl);
+    assert_eq!(InputFormat::from_path("transactions.csv.gz"), InputFormat::Csv);
+}
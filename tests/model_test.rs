@@ -0,0 +1,297 @@
+//! Random transaction sequences are run through `PaymentEngine` and through a sequential reference model,
+//! the final accounts and the per-transaction outcomes have to be identical.
+
+use std::collections::{BTreeMap, HashMap};
+use payment_engine::config::EngineConfig;
+use payment_engine::payment_engine::PaymentEngine;
+use payment_engine::transaction::{OutcomeStatus, TransactionEntity, TransactionType};
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+
+const CLIENTS: u16 = 6;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Deposit { client: u16, amount: Decimal },
+    Withdrawal { client: u16, amount: Decimal },
+    // `target` picks one of the deposits made before, `foreign` sends the row from another client
+    Dispute { target: usize, foreign: bool, amount: Option<Decimal> },
+    Resolve { target: usize, foreign: bool, amount: Option<Decimal> },
+    Chargeback { target: usize, foreign: bool, amount: Option<Decimal> },
+    Transfer { client: u16, to_client: u16, amount: Decimal },
+}
+
+fn amount() -> impl Strategy<Value = Decimal> {
+    // Up to 4 fractional digits, so the default precision never changes them
+    (0i64..2_000_000, 0u32..=4).prop_map(|(mantissa, scale)| Decimal::new(mantissa, scale))
+}
+
+fn reference() -> impl Strategy<Value = (usize, bool, Option<Decimal>)> {
+    (any::<usize>(), prop::bool::weighted(0.1), prop::option::of(amount()))
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let client = 1..=CLIENTS;
+
+    prop_oneof![
+        30 => (client.clone(), amount()).prop_map(|(client, amount)| Op::Deposit { client, amount }),
+        25 => (client.clone(), amount()).prop_map(|(client, amount)| Op::Withdrawal { client, amount }),
+        15 => reference().prop_map(|(target, foreign, amount)| Op::Dispute { target, foreign, amount }),
+        10 => reference().prop_map(|(target, foreign, amount)| Op::Resolve { target, foreign, amount }),
+        5 => reference().prop_map(|(target, foreign, amount)| Op::Chargeback { target, foreign, amount }),
+        10 => (client.clone(), client, amount()).prop_map(|(client, to_client, amount)| Op::Transfer { client, to_client, amount }),
+    ]
+}
+
+// Turns the ops into rows with unique tx ids, disputes reference the deposits made before them
+fn transactions(ops: &[Op]) -> Vec<TransactionEntity> {
+    let mut deposits: Vec<(u16, u32)> = Vec::new();
+    let mut transactions = Vec::new();
+
+    for (tx, op) in (1..).zip(ops) {
+        let reference = |target: usize, foreign: bool| {
+            let (client, tx) = deposits.get(target % deposits.len().max(1)).copied().unwrap_or((1, tx));
+            let client = if foreign { client % CLIENTS + 1 } else { client };
+            (client, tx)
+        };
+
+        let transaction = match op.clone() {
+            Op::Deposit { client, amount } => {
+                deposits.push((client, tx));
+                TransactionEntity::new(TransactionType::Deposit, client, tx, Some(amount))
+            }
+            Op::Withdrawal { client, amount } => TransactionEntity::new(TransactionType::Withdrawal, client, tx, Some(amount)),
+            Op::Dispute { target, foreign, amount } => {
+                let (client, tx) = reference(target, foreign);
+                TransactionEntity::new(TransactionType::Dispute, client, tx, amount)
+            }
+            Op::Resolve { target, foreign, amount } => {
+                let (client, tx) = reference(target, foreign);
+                TransactionEntity::new(TransactionType::Resolve, client, tx, amount)
+            }
+            Op::Chargeback { target, foreign, amount } => {
+                let (client, tx) = reference(target, foreign);
+                TransactionEntity::new(TransactionType::Chargeback, client, tx, amount)
+            }
+            Op::Transfer { client, to_client, amount } => TransactionEntity {
+                to_client: Some(to_client),
+                ..TransactionEntity::new(TransactionType::Transfer, client, tx, Some(amount))
+            },
+        };
+
+        transactions.push(transaction);
+    }
+
+    transactions
+}
+
+#[derive(Debug, Default)]
+struct ModelDeposit {
+    amount: Decimal,
+    disputed: Decimal,
+    settled: Decimal,
+    charged_back: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct ModelAccount {
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+}
+
+/// Sequential implementation of the rules, one transaction at a time without workers
+#[derive(Debug, Default)]
+struct Model {
+    accounts: BTreeMap<u16, ModelAccount>,
+    deposits: HashMap<(u16, u32), ModelDeposit>,
+}
+
+impl Model {
+    fn apply(&mut self, transaction: &TransactionEntity) -> Result<(), ()> {
+        let client = transaction.client;
+        let amount = transaction.amount.unwrap_or_default();
+        let key = (client, transaction.tx);
+
+        if transaction.transaction_type == TransactionType::Transfer {
+            return self.transfer(transaction);
+        }
+
+        // Open disputes are still settled on locked accounts
+        let account = *self.accounts.entry(client).or_default();
+        let settles = matches!(transaction.transaction_type, TransactionType::Resolve | TransactionType::Chargeback);
+        if account.locked && !settles {
+            return Err(());
+        }
+
+        match transaction.transaction_type {
+            TransactionType::Deposit => {
+                self.account(client).available += amount;
+                self.deposits.insert(key, ModelDeposit { amount, ..Default::default() });
+            }
+            TransactionType::Withdrawal => {
+                if amount <= Decimal::ZERO || amount > account.available {
+                    return Err(());
+                }
+                self.account(client).available -= amount;
+            }
+            TransactionType::Dispute => {
+                let deposit = self.deposits.get_mut(&key).ok_or(())?;
+                let undisputed = deposit.amount - deposit.disputed - deposit.settled;
+                if deposit.charged_back || undisputed <= Decimal::ZERO {
+                    return Err(());
+                }
+
+                let amount = transaction.amount.unwrap_or(undisputed);
+                if amount <= Decimal::ZERO || amount > undisputed || amount > account.available {
+                    return Err(());
+                }
+
+                deposit.disputed += amount;
+                let account = self.account(client);
+                account.available -= amount;
+                account.held += amount;
+            }
+            TransactionType::Resolve | TransactionType::Chargeback => {
+                let deposit = self.deposits.get_mut(&key).ok_or(())?;
+                if deposit.disputed <= Decimal::ZERO {
+                    return Err(());
+                }
+
+                let amount = transaction.amount.unwrap_or(deposit.disputed);
+                if amount <= Decimal::ZERO || amount > deposit.disputed {
+                    return Err(());
+                }
+
+                deposit.disputed -= amount;
+                deposit.settled += amount;
+                let chargeback = transaction.transaction_type == TransactionType::Chargeback;
+                deposit.charged_back |= chargeback;
+
+                let account = self.account(client);
+                account.held -= amount;
+                if chargeback {
+                    account.locked = true;
+                } else {
+                    account.available += amount;
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    fn transfer(&mut self, transaction: &TransactionEntity) -> Result<(), ()> {
+        let amount = transaction.amount.unwrap_or_default();
+        let to_client = match transaction.to_client {
+            Some(to_client) if to_client != transaction.client => to_client,
+            _ => return Err(()),
+        };
+
+        let source = self.account(transaction.client);
+        if source.locked || amount <= Decimal::ZERO || amount > source.available {
+            return Err(());
+        }
+        source.available -= amount;
+
+        // The destination is created even if it rejects the credit
+        let destination = self.account(to_client);
+        if destination.locked {
+            self.account(transaction.client).available += amount;
+            return Err(());
+        }
+        destination.available += amount;
+
+        Ok(())
+    }
+
+    fn account(&mut self, client: u16) -> &mut ModelAccount {
+        self.accounts.entry(client).or_default()
+    }
+}
+
+fn engine_config() -> EngineConfig {
+    EngineConfig { check_invariants: true, double_entry: true, history: true, ..Default::default() }
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn model_accounts(model: &Model) -> Vec<(u16, ModelAccount)> {
+    model.accounts.iter().map(|(client, account)| (*client, *account)).collect()
+}
+
+async fn engine_accounts(engine: &PaymentEngine) -> Vec<(u16, ModelAccount)> {
+    engine.get_account_entities(true).await.into_iter()
+        .map(|account| (account.client, ModelAccount { available: account.available, held: account.held, locked: account.locked }))
+        .collect()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn engine_matches_model(ops in prop::collection::vec(op(), 0..300)) {
+        let transactions = transactions(&ops);
+
+        let mut model = Model::default();
+        for transaction in transactions.iter() {
+            let _ = model.apply(transaction);
+        }
+
+        let (accounts, faulted) = runtime().block_on(async {
+            let mut engine = PaymentEngine::with_config(engine_config());
+            for transaction in transactions.iter() {
+                engine.process_transaction(transaction.clone()).await.unwrap();
+            }
+            // Verifies the double-entry ledger as well
+            engine.shutdown().await.unwrap();
+
+            (engine_accounts(&engine).await, engine.faulted_clients().await)
+        });
+
+        // A violated invariant panics in the worker and faults the account
+        prop_assert!(faulted.is_empty(), "faulted clients {:?}", faulted);
+        prop_assert_eq!(accounts, model_accounts(&model));
+    }
+
+    #[test]
+    fn outcomes_match_model(ops in prop::collection::vec(op(), 0..300)) {
+        let transactions = transactions(&ops);
+
+        let mut model = Model::default();
+        let expected: Vec<OutcomeStatus> = transactions.iter()
+            .map(|transaction| match model.apply(transaction) {
+                Ok(()) => OutcomeStatus::Applied,
+                Err(()) => OutcomeStatus::Rejected,
+            })
+            .collect();
+
+        let (outcomes, accounts) = runtime().block_on(async {
+            let mut engine = PaymentEngine::with_config(engine_config());
+
+            // Everything is submitted before the first outcome is awaited, like pipelined producers do
+            let mut pending = Vec::new();
+            for transaction in transactions.iter() {
+                pending.push(engine.submit_transaction(transaction.clone()).await.unwrap());
+            }
+
+            let mut outcomes = Vec::new();
+            for pending in pending {
+                outcomes.push(pending.outcome().await.status);
+            }
+            engine.shutdown().await.unwrap();
+
+            (outcomes, engine_accounts(&engine).await)
+        });
+
+        prop_assert_eq!(outcomes, expected);
+        prop_assert_eq!(accounts, model_accounts(&model));
+    }
+}
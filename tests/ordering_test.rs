@@ -0,0 +1,168 @@
+//! Ordering contract of `PaymentEngine`: every transaction gets the next sequence number when it enters the
+//! engine and the transactions of a client are applied in that order, whatever the engine mode.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use payment_engine::config::{EngineConfig, EngineMode, InputFormat};
+use payment_engine::generator::{Generator, GeneratorConfig, TypeWeights};
+use payment_engine::history::StatementFormat;
+use payment_engine::payment_engine::PaymentEngine;
+use payment_engine::transaction::{OutcomeStatus, TransactionEntity, TransactionOutcome, TransactionType};
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+const CLIENTS: u16 = 40;
+
+fn workload(seed: u64) -> Vec<TransactionEntity> {
+    let config = GeneratorConfig {
+        rows: 10_000,
+        clients: CLIENTS,
+        seed,
+        weights: TypeWeights { transfer: 10, ..Default::default() },
+        partial_dispute_ratio: 0.3,
+        ..Default::default()
+    };
+
+    let mut input = Vec::new();
+    Generator::new(config).write(InputFormat::Jsonl, &mut input).unwrap();
+    input.split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect()
+}
+
+fn engine(mode: EngineMode, channel_size: Option<usize>) -> PaymentEngine {
+    PaymentEngine::with_config(EngineConfig { mode, channel_size, history: true, check_invariants: true, ..Default::default() })
+}
+
+// Sequence numbers in the history of the client, a rolled back transfer shows up twice with the same number
+async fn history_seqs(engine: &PaymentEngine, client: u16) -> Vec<u64> {
+    let mut output = Vec::new();
+    if engine.export_statement(client, StatementFormat::Jsonl, &mut output).await.is_err() {
+        return Vec::new();
+    }
+
+    output.split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice::<Value>(line).unwrap()["seq"].as_u64().unwrap())
+        .collect()
+}
+
+// Every client must have applied exactly its applied transactions (both sides of transfers), in sequence order
+async fn assert_client_fifo(engine: &PaymentEngine, transactions: &[TransactionEntity], outcomes: &[TransactionOutcome]) {
+    let mut expected: HashMap<u16, Vec<u64>> = HashMap::new();
+    for (transaction, outcome) in transactions.iter().zip(outcomes) {
+        if outcome.status == OutcomeStatus::Applied {
+            let seq = outcome.seq.unwrap();
+            expected.entry(transaction.client).or_default().push(seq);
+            if transaction.transaction_type == TransactionType::Transfer {
+                expected.entry(transaction.to_client.unwrap()).or_default().push(seq);
+            }
+        }
+    }
+
+    let rejected: Vec<u64> = outcomes.iter()
+        .filter(|outcome| outcome.status == OutcomeStatus::Rejected)
+        .map(|outcome| outcome.seq.unwrap())
+        .collect();
+
+    for client in 1..=CLIENTS {
+        let mut seqs = history_seqs(engine, client).await;
+        assert!(seqs.windows(2).all(|pair| pair[0] <= pair[1]), "history of client {} is out of order: {:?}", client, seqs);
+
+        seqs.dedup();
+        seqs.retain(|seq| !rejected.contains(seq));
+        assert_eq!(seqs, expected.remove(&client).unwrap_or_default(), "client {}", client);
+    }
+}
+
+async fn check_single_producer(mode: EngineMode, channel_size: Option<usize>) {
+    let transactions = workload(11);
+    let mut engine = engine(mode, channel_size);
+
+    // Everything is submitted before any outcome is awaited, so the workers are busy concurrently
+    let mut pending = Vec::new();
+    for transaction in transactions.iter() {
+        pending.push(engine.submit_transaction(transaction.clone()).await.unwrap());
+    }
+
+    let mut outcomes = Vec::new();
+    for pending in pending {
+        outcomes.push(pending.outcome().await);
+    }
+    engine.shutdown().await.unwrap();
+
+    let seqs: Vec<u64> = outcomes.iter().map(|outcome| outcome.seq.unwrap()).collect();
+    assert_eq!(seqs, (1..=transactions.len() as u64).collect::<Vec<_>>());
+    assert_client_fifo(&engine, &transactions, &outcomes).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_client_fifo_actor_per_client() {
+    check_single_producer(EngineMode::ActorPerClient, Some(1)).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_client_fifo_sharded() {
+    check_single_producer(EngineMode::Sharded(3), Some(1)).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_client_fifo_synchronous() {
+    check_single_producer(EngineMode::Synchronous, None).await;
+}
+
+// Producers sharing the engine like the server connections do, each of them keeps the order of its own clients
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_client_fifo_with_concurrent_producers() {
+    const PRODUCERS: u16 = 4;
+    let engine = Arc::new(Mutex::new(engine(EngineMode::ActorPerClient, Some(2))));
+
+    // Transfers would cross the producers, so every producer gets the clients `client % PRODUCERS == producer`
+    let transactions: Vec<TransactionEntity> = workload(23).into_iter()
+        .filter(|transaction| transaction.transaction_type != TransactionType::Transfer)
+        .collect();
+
+    let mut producers = Vec::new();
+    for producer in 0..PRODUCERS {
+        let engine = engine.clone();
+        let own: Vec<TransactionEntity> = transactions.iter()
+            .filter(|transaction| transaction.client % PRODUCERS == producer)
+            .cloned()
+            .collect();
+
+        producers.push(tokio::spawn(async move {
+            let mut pending = Vec::new();
+            for transaction in own.iter() {
+                pending.push(engine.lock().await.submit_transaction(transaction.clone()).await.unwrap());
+                tokio::task::yield_now().await;
+            }
+
+            let mut outcomes = Vec::new();
+            for pending in pending {
+                outcomes.push(pending.outcome().await);
+            }
+            (own, outcomes)
+        }));
+    }
+
+    let mut all_transactions = Vec::new();
+    let mut all_outcomes = Vec::new();
+    for producer in producers {
+        let (own, outcomes) = producer.await.unwrap();
+        let seqs: Vec<u64> = outcomes.iter().map(|outcome| outcome.seq.unwrap()).collect();
+        assert!(seqs.windows(2).all(|pair| pair[0] < pair[1]));
+
+        all_transactions.extend(own);
+        all_outcomes.extend(outcomes);
+    }
+
+    let mut engine = engine.lock().await;
+    engine.shutdown().await.unwrap();
+
+    let mut seqs: Vec<u64> = all_outcomes.iter().map(|outcome| outcome.seq.unwrap()).collect();
+    seqs.sort();
+    assert_eq!(seqs, (1..=transactions.len() as u64).collect::<Vec<_>>());
+    assert_client_fifo(&engine, &all_transactions, &all_outcomes).await;
+}
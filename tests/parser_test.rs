@@ -0,0 +1,130 @@
+//! Differential test of the fast CSV path against serde: both must accept the same records with the same
+//! transactions and reject the same records with the same errors.
+
+use csv::{ByteRecord, ReaderBuilder, StringRecord};
+use payment_engine::fast_csv::RecordParser;
+use payment_engine::transaction::TransactionEntity;
+use proptest::prelude::*;
+
+const REQUIRED: [&str; 3] = ["type", "client", "tx"];
+const OPTIONAL: [&str; 5] = ["amount", "currency", "to_client", "timestamp", "note"];
+
+// Mostly values the fast path takes, so that both paths are compared on accepted records as well
+fn value(column: &'static str) -> BoxedStrategy<Vec<u8>> {
+    let (valid, tricky): (&[&str], &[&str]) = match column {
+        "type" => (&["deposit", "withdrawal", "dispute", "resolve", "chargeback", "transfer", "credit_limit"], &["Deposit", "creditlimit", ""]),
+        "client" | "to_client" => (&["1", "65535", "0x1f", "+3", "01"], &["65536", "0xG", "-1", "0x", ""]),
+        "tx" => (&["1", "4294967295", "0x10", "+0"], &["4294967296", "1.0", ""]),
+        "amount" => (&["1.0", "0.0001", "1.00001", "-2.5", ".5", "5.", ""], &["1e3", "1_0", "abc", "79228162514264337593543950336"]),
+        "currency" => (&["USD", "eur", "", "\"A,B\""], &["\"\""]),
+        "timestamp" => (&["1700000000", "0x0", ""], &["18446744073709551616", "1.5"]),
+        _ => (&["x", "", "\"quoted \"\" text\""], &["\"a\nb\""]),
+    };
+
+    let value = prop_oneof![4 => prop::sample::select(valid), 1 => prop::sample::select(tricky)];
+    let padding = prop_oneof![6 => Just(""), 1 => Just(" "), 1 => Just("\t"), 1 => Just("\u{a0}"), 1 => Just(" \u{2003}")];
+    (value, padding.clone(), padding, prop::bool::weighted(0.01))
+        .prop_map(|(value, before, after, invalid)| {
+            let mut bytes = format!("{}{}{}", before, value, after).into_bytes();
+            if invalid {
+                bytes.push(0xff);
+            }
+            bytes
+        })
+        .boxed()
+}
+
+fn input() -> impl Strategy<Value = Vec<u8>> {
+    // The required columns with some of the optional ones, rarely without a required one or with a duplicate
+    let headers = (prop::sample::subsequence(OPTIONAL.to_vec(), 0..=5), 0..20usize)
+        .prop_flat_map(|(optional, variant)| {
+            let mut columns = REQUIRED.to_vec();
+            columns.extend(optional);
+            match variant {
+                0 => {
+                    columns.remove(0);
+                }
+                1 => columns.push("amount"),
+                _ => {}
+            }
+            Just(columns).prop_shuffle()
+        });
+
+    headers.prop_flat_map(|columns| {
+        let row = columns.iter().map(|column| value(column)).collect::<Vec<_>>();
+        // Rows may be shorter or longer than the header
+        let row = (row, 0..20u8).prop_map(|(mut fields, variant)| {
+            match variant {
+                0 => {
+                    fields.pop();
+                }
+                1 => fields.push(b"9".to_vec()),
+                _ => {}
+            }
+            fields
+        });
+
+        (Just(columns), prop::collection::vec(row, 1..20))
+    })
+    .prop_map(|(columns, rows)| {
+        let mut input = columns.join(",").into_bytes();
+        for row in rows {
+            input.push(b'\n');
+            input.extend(row.join(&b","[..]));
+        }
+        input
+    })
+}
+
+type Parsed = Vec<Result<Result<TransactionEntity, String>, String>>;
+
+// The serde path as it was before the fast path
+fn parse_with_serde(input: &[u8]) -> Parsed {
+    let mut reader = ReaderBuilder::new().trim(csv::Trim::All).flexible(true).from_reader(input);
+    let headers = reader.headers().unwrap().clone();
+
+    let mut rows = Vec::new();
+    let mut record = StringRecord::new();
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(true) => rows.push(Ok(record.deserialize::<TransactionEntity>(Some(&headers)).map_err(|e| e.to_string()))),
+            Ok(false) => break,
+            Err(err) => {
+                rows.push(Err(err.to_string()));
+                break;
+            }
+        }
+    }
+
+    rows
+}
+
+// Read like by the app, the parser trims the fields itself
+fn parse_fast(input: &[u8]) -> Parsed {
+    let mut reader = ReaderBuilder::new().trim(csv::Trim::Headers).flexible(true).from_reader(input);
+    let parser = RecordParser::new(reader.headers().unwrap());
+
+    let mut rows = Vec::new();
+    let mut record = ByteRecord::new();
+    while reader.read_byte_record(&mut record).unwrap() {
+        let row = parser.parse(&record);
+        let failed = row.is_err();
+        rows.push(row);
+        if failed {
+            break;
+        }
+    }
+
+    rows
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2000))]
+
+    #[test]
+    fn fast_path_matches_serde(input in input()) {
+        // The header itself must be valid UTF-8 for both readers
+        prop_assume!(std::str::from_utf8(input.split(|byte| *byte == b'\n').next().unwrap()).is_ok());
+        prop_assert_eq!(parse_fast(&input), parse_with_serde(&input), "{}", String::from_utf8_lossy(&input));
+    }
+}
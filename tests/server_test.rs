@@ -0,0 +1,228 @@
+use std::net::SocketAddr;
+use payment_engine::config::EngineConfig;
+use payment_engine::ingest::{read_frame, serve_ingest, serve_ingest_until, write_frame};
+use payment_engine::payment_engine::PaymentEngine;
+use payment_engine::server::{serve, serve_until, ServerState};
+use payment_engine::shutdown::Signal;
+use tokio::sync::watch;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn start_server(snapshot: Option<std::path::PathBuf>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = ServerState::new(PaymentEngine::with_config(EngineConfig::default()), snapshot);
+
+    tokio::spawn(async move {
+        serve(listener, state).await.unwrap();
+    });
+
+    addr
+}
+
+// Minimal HTTP/1.1 client, returns the status code and the body
+async fn request(addr: SocketAddr, method: &str, path: &str, body: Option<Value>) -> (u16, String) {
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method, path, addr, body.len(), body
+    );
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").map(|(_, body)| body.to_string()).unwrap_or_default();
+    (status, body)
+}
+
+async fn submit(addr: SocketAddr, transaction: Value) -> Value {
+    let (status, body) = request(addr, "POST", "/transactions", Some(transaction)).await;
+    assert_eq!(status, 200, "{}", body);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_submit_and_query_accounts() {
+    let addr = start_server(None).await;
+
+    let outcome = submit(addr, json!({"type": "deposit", "client": 1, "tx": 1, "amount": "100.5"})).await;
+    assert_eq!(outcome, json!({"client": 1, "tx": 1, "type": "deposit", "seq": 1, "status": "applied"}));
+
+    let outcome = submit(addr, json!({"type": "withdrawal", "client": 1, "tx": 2, "amount": "200"})).await;
+    assert_eq!(outcome["status"], "rejected");
+    assert!(outcome["error"].is_string());
+
+    submit(addr, json!({"type": "deposit", "client": 2, "tx": 3, "amount": "5"})).await;
+    let outcome = submit(addr, json!({"type": "dispute", "client": 2, "tx": 3})).await;
+    assert_eq!(outcome["status"], "applied");
+    let outcome = submit(addr, json!({"type": "transfer", "client": 1, "tx": 4, "amount": "0.5", "to_client": 2})).await;
+    assert_eq!(outcome["status"], "applied");
+
+    let (status, body) = request(addr, "GET", "/accounts/1", None).await;
+    assert_eq!(status, 200);
+    let account: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(account, json!([{"client": 1, "currency": "USD", "available": "100.0", "held": "0", "total": "100.0", "locked": false}]));
+
+    let (status, body) = request(addr, "GET", "/accounts", None).await;
+    assert_eq!(status, 200);
+    let accounts: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(accounts.as_array().unwrap().len(), 2);
+    assert_eq!(accounts[1]["held"], "5");
+    assert_eq!(accounts[1]["total"], "5.5");
+
+    let (status, _) = request(addr, "GET", "/accounts/3", None).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn test_invalid_transaction_is_rejected() {
+    let addr = start_server(None).await;
+
+    let (status, _) = request(addr, "POST", "/transactions", Some(json!({"type": "unknown", "client": 1, "tx": 1}))).await;
+    assert!((400..500).contains(&status));
+}
+
+#[tokio::test]
+async fn test_snapshot() {
+    let path = std::env::temp_dir().join(format!("payment_engine_snapshot_{}.csv", std::process::id()));
+    let addr = start_server(Some(path.clone())).await;
+
+    submit(addr, json!({"type": "deposit", "client": 2, "tx": 1, "amount": "3.25"})).await;
+    submit(addr, json!({"type": "deposit", "client": 1, "tx": 2, "amount": "1"})).await;
+
+    let (status, body) = request(addr, "POST", "/snapshot", None).await;
+    assert_eq!(status, 200, "{}", body);
+    let response: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(response["accounts"], 2);
+
+    let snapshot = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(snapshot, "\
+client,currency,available,held,total,locked
+1,USD,1,0,1,false
+2,USD,3.25,0,3.25,false
+");
+}
+
+#[tokio::test]
+async fn test_snapshot_not_configured() {
+    let addr = start_server(None).await;
+
+    let (status, _) = request(addr, "POST", "/snapshot", None).await;
+    assert_eq!(status, 409);
+}
+
+#[tokio::test]
+async fn test_ingest_pipelined_acks() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = ServerState::new(PaymentEngine::new(), None);
+    let engine = state.engine();
+    tokio::spawn(async move {
+        serve_ingest(listener, state.engine()).await.unwrap();
+    });
+
+    let transactions = [
+        json!({"type": "deposit", "client": 1, "tx": 1, "amount": "10"}),
+        json!({"type": "deposit", "client": 2, "tx": 2, "amount": "5"}),
+        json!({"type": "withdrawal", "client": 1, "tx": 3, "amount": "20"}),
+        json!({"type": "dispute", "client": 2, "tx": 2}),
+        json!({"type": "withdrawal", "client": 1, "tx": 4, "amount": "4"}),
+    ];
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut reader, mut writer) = stream.into_split();
+
+    // Everything is sent before reading any acknowledgement
+    for transaction in transactions.iter() {
+        write_frame(&mut writer, transaction.to_string().as_bytes()).await.unwrap();
+    }
+    write_frame(&mut writer, b"not json").await.unwrap();
+    writer.shutdown().await.unwrap();
+
+    let mut acks = Vec::new();
+    while let Some(payload) = read_frame(&mut reader).await.unwrap() {
+        acks.push(serde_json::from_slice::<Value>(&payload).unwrap());
+    }
+
+    assert_eq!(acks.len(), 6);
+    let statuses: Vec<&str> = acks.iter().map(|ack| ack["status"].as_str().unwrap()).collect();
+    assert_eq!(statuses, ["applied", "applied", "rejected", "applied", "applied", "rejected"]);
+    let txs: Vec<u64> = acks[..5].iter().map(|ack| ack["tx"].as_u64().unwrap()).collect();
+    assert_eq!(txs, [1, 2, 3, 2, 4]);
+    let seqs: Vec<u64> = acks[..5].iter().map(|ack| ack["seq"].as_u64().unwrap()).collect();
+    assert_eq!(seqs, [1, 2, 3, 4, 5]);
+    assert!(acks[5]["error"].as_str().unwrap().starts_with("Error deserializing transaction"));
+
+    let accounts = engine.lock().await.list_accounts().await.unwrap();
+    assert_eq!(accounts[0].total.to_string(), "6");
+    assert_eq!(accounts[1].held.to_string(), "5");
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = ServerState::new(PaymentEngine::new(), None);
+    let (sender, receiver) = watch::channel(None);
+
+    let server_state = state.clone();
+    let server = tokio::spawn(async move {
+        serve_until(listener, server_state, receiver).await.unwrap();
+    });
+
+    submit(addr, json!({"type": "deposit", "client": 1, "tx": 1, "amount": "2"})).await;
+    sender.send(Some(Signal::Interrupt)).unwrap();
+    server.await.unwrap();
+    assert!(TcpStream::connect(addr).await.is_err());
+
+    let mut output = Vec::new();
+    state.shutdown(&mut output).await.unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "client,currency,available,held,total,locked\n1,USD,2,0,2,false\n");
+    assert!(state.engine().lock().await.is_stopped());
+}
+
+#[tokio::test]
+async fn test_ingest_acknowledges_read_frames_on_shutdown() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = ServerState::new(PaymentEngine::new(), None);
+    let (sender, receiver) = watch::channel(None);
+
+    let engine = state.engine();
+    let server = tokio::spawn(async move {
+        serve_ingest_until(listener, engine, receiver).await.unwrap();
+    });
+
+    let (mut reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    // Keeps sending while the stop is requested, the frames after it are not read
+    let producer = tokio::spawn(async move {
+        for tx in 1..=20_000 {
+            let deposit = json!({"type": "deposit", "client": 1, "tx": tx, "amount": "1"});
+            if write_frame(&mut writer, deposit.to_string().as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut applied = 0;
+    while let Some(payload) = read_frame(&mut reader).await.unwrap() {
+        assert_eq!(serde_json::from_slice::<Value>(&payload).unwrap()["status"], "applied");
+        applied += 1;
+        if applied == 100 {
+            sender.send(Some(Signal::Interrupt)).unwrap();
+        }
+    }
+
+    // The connection was closed after its last acknowledgement, before the server returned
+    server.await.unwrap();
+    producer.abort();
+
+    let mut output = Vec::new();
+    state.shutdown(&mut output).await.unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), format!("client,currency,available,held,total,locked\n1,USD,{0},0,{0},false\n", applied));
+}